@@ -185,7 +185,13 @@ fn testnet_genesis(
                 .to_vec(),
         },
         balances: polkem_mediator_runtime::BalancesConfig {
-            balances: endowed_accounts.iter().cloned().map(|k| (k, 1 << 60)).collect(),
+            // The grid operator pays for what the grid buys from its free balance
+            balances: endowed_accounts
+                .iter()
+                .cloned()
+                .chain([polkem_mediator_runtime::GridOperator::get()])
+                .map(|k| (k, 1 << 60))
+                .collect(),
         },
         parachain_info: polkem_mediator_runtime::ParachainInfoConfig { parachain_id: id },
         collator_selection: polkem_mediator_runtime::CollatorSelectionConfig {
@@ -213,5 +219,6 @@ fn testnet_genesis(
         polkadot_xcm: polkem_mediator_runtime::PolkadotXcmConfig {
             safe_xcm_version: Some(SAFE_XCM_VERSION),
         },
+        mediator: Default::default(),
     }
 }
@@ -4,16 +4,267 @@ use super::*;
 
 #[allow(unused)]
 use crate::Pallet as Mediator;
-use frame_benchmarking::{benchmarks, impl_benchmark_test_suite, whitelisted_caller};
+use frame_benchmarking::{
+	account, benchmarks, impl_benchmark_test_suite, whitelisted_caller, BenchmarkError,
+};
+use frame_support::{
+	sp_runtime::traits::{Bounded, Zero},
+	sp_std::vec,
+	traits::{Currency, EnsureOrigin, Get},
+};
 use frame_system::RawOrigin;
 
+const SEED: u32 = 0;
+
+/// Period the orders of the benchmarks are placed for, whose gate is open at genesis
+const PERIOD: PeriodIndex = 1;
+
+/// Fund the account for any order and register it as a participant.
+fn register<T: Config>(who: &T::AccountId, metering_point: MeterId) {
+	T::Currency::make_free_balance_be(who, BalanceOf::<T>::max_value() / 4u32.into());
+	let participant = Participant {
+		role: Role::Prosumer,
+		microgrid: None,
+		metering_point,
+		active: true,
+		deposit: Zero::zero(),
+		open_orders: 0,
+	};
+	Participants::<T>::insert(who, participant);
+}
+
+fn participant<T: Config>(index: u32) -> T::AccountId {
+	let who = account("participant", index, SEED);
+	register::<T>(&who, index.into());
+	who
+}
+
+/// Place requests, each for twice the least amount, and offers, each for the least amount, from
+/// accounts of their own. Half of the requests are served by the offers and the other half by the
+/// grid, so that every order processed forms a trade.
+fn fill_book<T: Config>(requests: u32, offers: u32) -> Result<(), BenchmarkError> {
+	let amount = T::MinOrderAmount::get();
+	for i in 0..requests {
+		let origin = RawOrigin::Signed(participant::<T>(i)).into();
+		let price = T::MaxPrice::get();
+		Mediator::<T>::submit_request(origin, amount * 2, price, PERIOD, Default::default())?;
+	}
+	for i in 0..offers {
+		let origin = RawOrigin::Signed(participant::<T>(requests + i)).into();
+		let source = EnergySource::Pv;
+		Mediator::<T>::submit_offer(origin, amount, T::MinPrice::get(), PERIOD, source)?;
+	}
+	CurrentGridTariff::<T>::put(GridTariff {
+		import_price: T::MinPrice::get(),
+		export_price: T::MinPrice::get(),
+		import_capacity: u16::MAX,
+		export_capacity: u16::MAX,
+	});
+	Ok(())
+}
+
+/// Preference of a request for some sources, excluding all others
+fn preference() -> SourcePreference {
+	let preferred = vec![EnergySource::Pv, EnergySource::Wind];
+	let excluded = vec![EnergySource::Chp, EnergySource::GridMix];
+	SourcePreference {
+		preferred: BoundedVec::truncate_from(preferred),
+		premium: 1,
+		excluded: BoundedVec::truncate_from(excluded),
+	}
+}
+
+fn close_gate<T: Config>() {
+	frame_system::Pallet::<T>::set_block_number(Mediator::<T>::gate_closure(PERIOD));
+}
+
 benchmarks! {
-	do_something {
-		let s in 0 .. 100;
+	submit_request {
+		fill_book::<T>(T::MaxOrdersPerPeriod::get() - 1, 0)?;
+		let caller: T::AccountId = whitelisted_caller();
+		register::<T>(&caller, 0);
+		let amount = T::MinOrderAmount::get();
+	}: _(RawOrigin::Signed(caller), amount, T::MaxPrice::get(), PERIOD, preference())
+	verify {
+		assert_eq!(Requests::<T>::get(PERIOD).len() as u32, T::MaxOrdersPerPeriod::get());
+	}
+
+	submit_offer {
+		fill_book::<T>(0, T::MaxOrdersPerPeriod::get() - 1)?;
+		let caller: T::AccountId = whitelisted_caller();
+		register::<T>(&caller, 0);
+		let amount = T::MinOrderAmount::get();
+	}: _(RawOrigin::Signed(caller), amount, T::MinPrice::get(), PERIOD, EnergySource::Wind)
+	verify {
+		assert_eq!(Offers::<T>::get(PERIOD).len() as u32, T::MaxOrdersPerPeriod::get());
+	}
+
+	cancel_order {
+		fill_book::<T>(T::MaxOrdersPerPeriod::get() - 1, 0)?;
+		let caller: T::AccountId = whitelisted_caller();
+		register::<T>(&caller, 0);
+		let origin = RawOrigin::Signed(caller.clone()).into();
+		let amount = T::MinOrderAmount::get();
+		let price = T::MaxPrice::get();
+		Mediator::<T>::submit_request(origin, amount, price, PERIOD, Default::default())?;
+		// The last order placed, found at the end of the book
+		let order_id = NextOrderId::<T>::get() - 1;
+	}: _(RawOrigin::Signed(caller), order_id)
+	verify {
+		assert!(OrderLocations::<T>::get(order_id).is_none());
+	}
+
+	amend_order {
+		fill_book::<T>(T::MaxOrdersPerPeriod::get() - 1, 0)?;
+		let caller: T::AccountId = whitelisted_caller();
+		register::<T>(&caller, 0);
+		let origin = RawOrigin::Signed(caller.clone()).into();
+		let amount = T::MinOrderAmount::get();
+		// Placed at the least value, so that amending it reserves more
+		let price = T::MinPrice::get();
+		Mediator::<T>::submit_request(origin, amount, price, PERIOD, Default::default())?;
+		let order_id = NextOrderId::<T>::get() - 1;
+	}: _(RawOrigin::Signed(caller), order_id, amount * 2, T::MaxPrice::get(), preference())
+	verify {
+		let book = Requests::<T>::get(PERIOD);
+		assert_eq!(book.iter().find(|o| o.id == order_id).map(|o| o.amount), Some(amount * 2));
+	}
+
+	register_meter {
+		let participant = participant::<T>(0);
+		let key: T::AccountId = account("key", 0, SEED);
+	}: _(RawOrigin::Root, 0, key.clone(), participant.clone())
+	verify {
+		assert_eq!(Meters::<T>::get(0), Some(Meter { key, participant }));
+	}
+
+	submit_meter_reading {
+		let participant = participant::<T>(0);
+		let key: T::AccountId = whitelisted_caller();
+		Meters::<T>::insert(0, Meter { key: key.clone(), participant: participant.clone() });
+		TradedEnergy::<T>::insert(PERIOD, &participant, 10);
+		let delivered = Mediator::<T>::period_start(PERIOD + 1);
+		frame_system::Pallet::<T>::set_block_number(delivered);
+	}: _(RawOrigin::Signed(key), 0, PERIOD, 10_000, 0)
+	verify {
+		assert!(Deliveries::<T>::contains_key(PERIOD, &participant));
+	}
+
+	register_participant {
+		let caller: T::AccountId = whitelisted_caller();
+		T::Currency::make_free_balance_be(&caller, BalanceOf::<T>::max_value() / 4u32.into());
+	}: _(RawOrigin::Signed(caller.clone()), Role::Prosumer, Some(0), 0)
+	verify {
+		assert!(Participants::<T>::contains_key(&caller));
+	}
+
+	deregister_participant {
 		let caller: T::AccountId = whitelisted_caller();
-	}: _(RawOrigin::Signed(caller), s)
+		T::Currency::make_free_balance_be(&caller, BalanceOf::<T>::max_value() / 4u32.into());
+		let origin = RawOrigin::Signed(caller.clone()).into();
+		Mediator::<T>::register_participant(origin, Role::Prosumer, None, 0)?;
+	}: _(RawOrigin::Signed(caller.clone()))
+	verify {
+		assert!(!Participants::<T>::contains_key(&caller));
+	}
+
+	set_participant_active {
+		let participant = participant::<T>(0);
+	}: _(RawOrigin::Root, participant.clone(), false)
+	verify {
+		assert_eq!(Participants::<T>::get(&participant).map(|p| p.active), Some(false));
+	}
+
+	set_grid_tariff {
+		let origin = T::GridOrigin::try_successful_origin()
+			.map_err(|_| BenchmarkError::Weightless)?;
+		let tariff = GridTariff {
+			import_price: T::MaxPrice::get(),
+			export_price: T::MinPrice::get(),
+			import_capacity: u16::MAX,
+			export_capacity: u16::MAX,
+		};
+	}: _<T::RuntimeOrigin>(origin, tariff, Some(PERIOD))
+	verify {
+		assert_eq!(ScheduledGridTariffs::<T>::get(PERIOD), Some(tariff));
+	}
+
+	set_participant_role {
+		let participant = participant::<T>(0);
+	}: _(RawOrigin::Root, participant.clone(), Role::GridOperator)
+	verify {
+		let role = Participants::<T>::get(&participant).map(|p| p.role);
+		assert_eq!(role, Some(Role::GridOperator));
+	}
+
+	clear_orders {
+		let o in 1 .. T::MaxOrdersPerPeriod::get();
+		// Takes one and a half steps per request to clear, so it is not finished by `o` steps
+		fill_book::<T>(T::MaxOrdersPerPeriod::get(), T::MaxOrdersPerPeriod::get())?;
+		close_gate::<T>();
+	}: {
+		Mediator::<T>::clear(PERIOD, o);
+	}
+	verify {
+		assert!(Clearing::<T>::exists());
+		assert_eq!(PeriodTrades::<T>::get(PERIOD).len() as u32, o);
+	}
+
+	finish_clearing {
+		let n in 1 .. T::MaxOrdersPerPeriod::get();
+		fill_book::<T>(n, n)?;
+		close_gate::<T>();
+		// Skip matching, leaving the period to be finished with a statement for every participant,
+		// half of them owing what they reserved for their requests to the other half
+		Mediator::<T>::clear(PERIOD, 0);
+		Clearing::<T>::mutate(|cursor| {
+			if let Some(cursor) = cursor {
+				cursor.next_request = n;
+				cursor.next_offer = n;
+			}
+		});
+		for i in 0..n * 2 {
+			let who: T::AccountId = account("participant", i, SEED);
+			let value = BalanceOf::<T>::from(T::MinPrice::get() as u32);
+			let statement = if i < n {
+				Statement { value_bought: value, ..Default::default() }
+			} else {
+				Statement { value_sold: value, ..Default::default() }
+			};
+			PeriodStatements::<T>::insert(who, statement);
+		}
+	}: {
+		Mediator::<T>::clear(PERIOD, 1);
+	}
+	verify {
+		assert_eq!(CurrentPeriod::<T>::get(), PERIOD + 1);
+		assert!(Requests::<T>::get(PERIOD).is_empty());
+	}
+
+	prune_period {
+		let t in 0 .. T::MaxPrunedPerBlock::get();
+		let e in 0 .. T::MaxPrunedPerBlock::get();
+		// Fill the period before the earliest one retained with trades
+		let seller = participant::<T>(0);
+		let buyer = participant::<T>(1);
+		for _ in 0..t {
+			let trade = Trade { amount: 1, price: 1, seller: seller.clone(), buyer: buyer.clone() };
+			Mediator::<T>::record_trade(PERIOD, trade, Certificate::default())?;
+		}
+		// Each trade put the net energy of the buyer and seller, which counts against `e`
+		let traded = TradedEnergy::<T>::iter_prefix(PERIOD).count() as u32;
+		for meter in traded..e {
+			let reading = MeterReading { imported_wh: 1, exported_wh: 0 };
+			MeterReadings::<T>::insert(PERIOD, MeterId::from(meter), reading);
+		}
+		PrunedPeriod::<T>::put(PERIOD);
+		CurrentPeriod::<T>::put(PERIOD + PeriodIndex::from(T::RetainedPeriods::get()) + 1);
+	}: {
+		Mediator::<T>::prune(t + e);
+	}
 	verify {
-		assert_eq!(Something::<T>::get(), Some(s));
+		assert!(PeriodTrades::<T>::get(PERIOD).is_empty());
+		assert_eq!(MeterReadings::<T>::iter_prefix(PERIOD).count(), 0);
 	}
 }
 
@@ -0,0 +1,45 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use std::time::Duration;
+
+use energy_trade_matching::{
+	energy_trade_matching::MatchingMode,
+	parallel::generate_trades_parallel,
+	scenario::{Scenario, ScenarioConfig},
+};
+
+const SEED: u64 = 42;
+
+fn ten_assets_per_mg_parallel(c: &mut Criterion) {
+	let Scenario { microgrids: partitions, grid_request, grid_offer } =
+		Scenario::generate(&ScenarioConfig {
+			seed: SEED,
+			consumers: 9_440,
+			producers: 9_440,
+			microgrids: 1_888,
+			..Default::default()
+		})
+		.expect("The scenario is valid");
+
+	c.bench_function("Ten assets for each of the 18,880 proposed MG (parallel)", |b| {
+		b.iter_batched(
+			|| partitions.clone(),
+			|mut partitions| {
+				generate_trades_parallel(
+					black_box(&mut partitions),
+					black_box(&grid_request),
+					black_box(&grid_offer),
+					MatchingMode::Price,
+				)
+			},
+			BatchSize::LargeInput,
+		)
+	});
+}
+
+criterion_group! {
+	name = benches;
+	config = Criterion::default().measurement_time(Duration::new(60, 0)).sample_size(30).confidence_level(0.98);
+	targets = ten_assets_per_mg_parallel
+}
+
+criterion_main!(benches);
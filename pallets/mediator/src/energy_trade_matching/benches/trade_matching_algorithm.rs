@@ -1,9 +1,14 @@
 use chrono::Local;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use rand::Rng;
 use std::{io::Write, time::Duration};
 
-use energy_trade_matching::energy_trade_matching::*;
+use energy_trade_matching::{
+	energy_trade_matching::*,
+	replay::RecordedRound,
+	scenario::{Scenario, ScenarioConfig},
+};
+
+const SEED: u64 = 42;
 
 fn one_asset_per_mg(c: &mut Criterion) {
 	let (mut requests, mut offers) = generate_requests_and_offers(944, 944);
@@ -23,7 +28,7 @@ fn one_asset_per_mg(c: &mut Criterion) {
 	let reqs_json = serde_json::to_string(&requests).unwrap();
 	let mut file = std::fs::File::create(format!(
 		"requests-{}.json",
-		Local::now().format("%Y-%m-%d@%H:%M:%S").to_string()
+		Local::now().format("%Y-%m-%d@%H:%M:%S")
 	))
 	.unwrap();
 	file.write_all(reqs_json.as_bytes()).unwrap();
@@ -31,7 +36,7 @@ fn one_asset_per_mg(c: &mut Criterion) {
 	let offs_json = serde_json::to_string(&offers).unwrap();
 	let mut file = std::fs::File::create(format!(
 		"offers-{}.json",
-		Local::now().format("%Y-%m-%d@%H:%M:%S").to_string()
+		Local::now().format("%Y-%m-%d@%H:%M:%S")
 	))
 	.unwrap();
 	file.write_all(offs_json.as_bytes()).unwrap();
@@ -85,10 +90,24 @@ fn large_overestimation(c: &mut Criterion) {
 	});
 }
 
+/// Replays the order dumps given by `REPLAY_REQUESTS` and `REPLAY_OFFERS`, e.g. those written by
+/// `one_asset_per_mg`, defaulting to the recorded round of the regression tests.
+fn recorded_round(c: &mut Criterion) {
+	let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/round_1");
+	let requests_path = std::env::var("REPLAY_REQUESTS")
+		.unwrap_or_else(|_| format!("{}/requests.json", fixture));
+	let offers_path =
+		std::env::var("REPLAY_OFFERS").unwrap_or_else(|_| format!("{}/offers.jsonl", fixture));
+	let (grid_request, grid_offer) = generate_grid_request_and_offer();
+	let round = RecordedRound::load(requests_path, offers_path, grid_request, grid_offer).unwrap();
+
+	c.bench_function("Recorded round replay", |b| b.iter(|| black_box(&round).replay()));
+}
+
 criterion_group! {
 	name = benches;
 	config = Criterion::default().measurement_time(Duration::new(60, 0)).sample_size(30).confidence_level(0.98);
-	targets = one_asset_per_mg, ten_assets_per_mg, block_transaction_limit_of_9_850, large_overestimation
+	targets = one_asset_per_mg, ten_assets_per_mg, block_transaction_limit_of_9_850, large_overestimation, recorded_round
 }
 
 criterion_main!(benches);
@@ -97,31 +116,17 @@ fn generate_requests_and_offers(
 	req_count: u32,
 	off_count: u32,
 ) -> (Vec<EnergyRequest>, Vec<EnergyOffer>) {
-	let min: u16 = 200;
-	let max: u16 = 1000;
-
-	let mut rng = rand::thread_rng();
-	let mut requests: Vec<EnergyRequest> = (0..req_count)
-		.map(|_| energy_request_factory(rng.gen_range(min..max), rng.gen_range(1.8..3.5)))
-		.collect();
-
-	let mut offers: Vec<EnergyOffer> = (0..off_count)
-		.map(|_| energy_offer_factory(rng.gen_range(min..max), rng.gen_range(2.0..4.0)))
-		.collect();
-
-	(requests, offers)
+	Scenario::generate(&ScenarioConfig {
+		seed: SEED,
+		consumers: req_count,
+		producers: off_count,
+		..Default::default()
+	})
+	.expect("The scenario is valid")
+	.orders()
 }
 
 fn generate_grid_request_and_offer() -> (EnergyRequest, EnergyOffer) {
-	let grid_request = EnergyRequest { amount: 20, price: 1.5, buyer: String::from("Grid_Buyer") };
-	let grid_offer = EnergyOffer { amount: 20, price: 2.6, seller: String::from("Grid_Seller") };
-	(grid_request, grid_offer)
-}
-
-fn energy_request_factory(amount: u16, price: f32) -> EnergyRequest {
-	EnergyRequest { amount, price, buyer: String::from("mg_buyer") }
-}
-
-fn energy_offer_factory(amount: u16, price: f32) -> EnergyOffer {
-	EnergyOffer { amount, price, seller: String::from("mg_seller") }
+	let scenario = Scenario::generate(&ScenarioConfig::default()).expect("The scenario is valid");
+	(scenario.grid_request, scenario.grid_offer)
 }
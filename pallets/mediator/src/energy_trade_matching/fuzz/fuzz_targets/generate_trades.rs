@@ -0,0 +1,104 @@
+//! Clears arbitrary rounds and checks the invariants of the resulting trades.
+//!
+//! Run with `cargo +nightly fuzz run generate_trades` from the crate directory.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use energy_trade_matching::{
+	energy_trade_matching::{
+		generate_trades_in_mode, EnergyOffer, EnergyRequest, EnergySource, MatchingMode,
+		SourcePreference,
+	},
+	invariants,
+};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Request {
+	amount: u16,
+	price: f32,
+	preferred: Option<u8>,
+	premium: f32,
+	excluded: Option<u8>,
+	budget: Option<f64>,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Offer {
+	amount: u16,
+	price: f32,
+	source: u8,
+	carbon_intensity: f32,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Round {
+	requests: Vec<Request>,
+	offers: Vec<Offer>,
+	grid_buying_price: f32,
+	grid_selling_price: f32,
+	grid_carbon_intensity: f32,
+	low_carbon: bool,
+}
+
+fn source(index: u8) -> EnergySource {
+	EnergySource::ALL[index as usize % EnergySource::ALL.len()]
+}
+
+fuzz_target!(|round: Round| {
+	let requests: Vec<_> = round
+		.requests
+		.iter()
+		.enumerate()
+		.map(|(i, r)| EnergyRequest {
+			amount: r.amount,
+			price: r.price,
+			buyer: format!("buyer_{}", i),
+			origin: SourcePreference {
+				preferred: r.preferred.map(source).into_iter().collect(),
+				premium: r.premium,
+				excluded: r.excluded.map(source).into_iter().collect(),
+			},
+			budget: r.budget,
+		})
+		.collect();
+	let offers: Vec<_> = round
+		.offers
+		.iter()
+		.enumerate()
+		.map(|(i, o)| EnergyOffer {
+			amount: o.amount,
+			price: o.price,
+			seller: format!("seller_{}", i),
+			source: source(o.source),
+			carbon_intensity: o.carbon_intensity,
+		})
+		.collect();
+	let grid_request = EnergyRequest {
+		price: round.grid_buying_price,
+		buyer: "grid".into(),
+		..Default::default()
+	};
+	let grid_offer = EnergyOffer {
+		price: round.grid_selling_price,
+		seller: "grid".into(),
+		carbon_intensity: round.grid_carbon_intensity,
+		..Default::default()
+	};
+	let mode = if round.low_carbon { MatchingMode::LowCarbon } else { MatchingMode::Price };
+
+	let trades = generate_trades_in_mode(
+		&mut requests.clone(),
+		&mut offers.clone(),
+		&grid_request,
+		&grid_offer,
+		mode,
+	);
+
+	if let Err(violation) =
+		invariants::check(&requests, &offers, &grid_request, &grid_offer, &trades)
+	{
+		panic!("{}", violation);
+	}
+});
@@ -0,0 +1,381 @@
+//! Offline clearing of recorded order books, without a chain.
+//!
+//! Reads requests, offers and grid prices from JSON, JSON Lines or CSV files, or one of them from
+//! stdin when its path is `-`, and writes the trades and a summary report of the round. Optional
+//! columns may be left empty in CSV.
+
+use std::{
+	collections::BTreeMap,
+	fs::File,
+	io::{self, Read, Write},
+	path::{Path, PathBuf},
+	process::ExitCode,
+};
+
+use clap::{Parser, ValueEnum};
+use serde::{
+	de::{value, DeserializeOwned, IntoDeserializer},
+	Deserialize, Serialize,
+};
+
+use energy_trade_matching::{
+	energy_trade_matching::{
+		generate_trades_in_mode, EnergyOffer, EnergyRequest, EnergySource, MarketPartition,
+		MatchingMode, SourcePreference, Trade,
+	},
+	hierarchical::{generate_hierarchical_trades, ClearingLevel},
+	replay::{read_dump, write_dump, DumpFormat, ReplayError},
+	report::RoundReport,
+	trade_id::TradeId,
+};
+
+#[derive(Parser, Debug)]
+#[command(name = "energy-match", about = "Clear energy requests and offers offline")]
+struct Cli {
+	/// Requests with `amount`, `price`, `buyer` and optionally `microgrid`, `origin` and `budget`,
+	/// as dumped by `replay::save_dump`. In CSV, the `origin` is given flat as `preferred_sources`,
+	/// `premium` and `excluded_sources`, sources being space separated, e.g. `Pv Wind`
+	#[arg(long)]
+	requests: PathBuf,
+	/// Offers with `amount`, `price`, `seller` and optionally `microgrid`, `carbon_intensity` in
+	/// grams of CO2 per unit and `source`, one of `Pv`, `Wind`, `Chp` or `GridMix`
+	#[arg(long)]
+	offers: PathBuf,
+	/// The grid's `request_price`, `offer_price` and optionally `carbon_intensity`, as a single
+	/// record, or in JSON also as a single object
+	#[arg(long)]
+	grid: PathBuf,
+	#[arg(long, value_enum, default_value_t = Strategy::Standard)]
+	strategy: Strategy,
+	/// Round the trades are identified by, as on chain
+	#[arg(long, default_value_t = 0)]
+	round: u64,
+	/// Prefer offers with a lower carbon intensity at equal price, with the standard or
+	/// hierarchical strategy
+	#[arg(long)]
+	low_carbon: bool,
+	/// Format of inputs read from stdin or without a known extension
+	#[arg(long, default_value = "json")]
+	input_format: DumpFormat,
+	/// Where to write the trades
+	#[arg(long, default_value = "-")]
+	trades: PathBuf,
+	/// Where to write the summary report, if anywhere
+	#[arg(long)]
+	report: Option<PathBuf>,
+	/// Format of outputs written to stdout or without a known extension
+	#[arg(long, default_value = "json")]
+	output_format: DumpFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Strategy {
+	/// Pool all orders and match them with `generate_trades`
+	Standard,
+	/// Match within microgrids, then across microgrids, then with the grid
+	Hierarchical,
+	/// Like hierarchical, but clearing microgrids concurrently
+	#[cfg(feature = "parallel")]
+	Parallel,
+}
+
+#[derive(Deserialize, Debug)]
+struct RequestRecord {
+	amount: u16,
+	price: f32,
+	buyer: String,
+	#[serde(default)]
+	microgrid: Option<String>,
+	#[serde(default)]
+	origin: Option<SourcePreference>,
+	#[serde(default)]
+	preferred_sources: String,
+	#[serde(default)]
+	premium: Option<f32>,
+	#[serde(default)]
+	excluded_sources: String,
+	#[serde(default)]
+	budget: Option<f64>,
+}
+
+impl RequestRecord {
+	fn origin(&self) -> Result<SourcePreference, ReplayError> {
+		let flat = !self.preferred_sources.is_empty()
+			|| self.premium.is_some()
+			|| !self.excluded_sources.is_empty();
+		match &self.origin {
+			Some(_) if flat => Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"Sources of a request are given both in `origin` and flat",
+			)
+			.into()),
+			Some(origin) => Ok(origin.clone()),
+			None => Ok(SourcePreference {
+				preferred: parse_sources(&self.preferred_sources)?,
+				premium: self.premium.unwrap_or_default(),
+				excluded: parse_sources(&self.excluded_sources)?,
+			}),
+		}
+	}
+}
+
+#[derive(Deserialize, Debug)]
+struct OfferRecord {
+	amount: u16,
+	price: f32,
+	seller: String,
+	#[serde(default)]
+	microgrid: Option<String>,
+	#[serde(default)]
+	source: Option<EnergySource>,
+	#[serde(default)]
+	carbon_intensity: Option<f32>,
+}
+
+/// A single record, or a list of them
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+	One(T),
+	Many(Vec<T>),
+}
+
+#[derive(Deserialize, Debug)]
+struct GridPrices {
+	/// Price at which the grid buys energy
+	request_price: f32,
+	/// Price at which the grid sells energy
+	offer_price: f32,
+	/// Grams of CO2 emitted per unit of energy the grid sells
+	#[serde(default)]
+	carbon_intensity: Option<f32>,
+	#[serde(default = "default_grid_id")]
+	buyer: String,
+	#[serde(default = "default_grid_id")]
+	seller: String,
+}
+
+fn default_grid_id() -> String {
+	String::from("grid")
+}
+
+/// A trade as written to the output, flat so that it fits a CSV row
+#[derive(Serialize, Debug)]
+struct TradeRecord<'a> {
+	id: TradeId,
+	amount: u16,
+	price: f32,
+	buyer: &'a str,
+	seller: &'a str,
+	level: Option<String>,
+	source: EnergySource,
+	premium: f32,
+	carbon_intensity: f32,
+	emissions: f64,
+}
+
+impl<'a> TradeRecord<'a> {
+	fn new(id: TradeId, trade: &'a Trade, level: Option<&ClearingLevel>) -> Self {
+		let level = level.map(|l| match l {
+			ClearingLevel::IntraMicrogrid(microgrid) => format!("intra:{}", microgrid),
+			ClearingLevel::InterMicrogrid => String::from("inter"),
+			ClearingLevel::Grid => String::from("grid"),
+		});
+		TradeRecord {
+			id,
+			amount: trade.amount,
+			price: trade.price,
+			buyer: &trade.buyer,
+			seller: &trade.seller,
+			level,
+			source: trade.certificate.source,
+			premium: trade.certificate.premium,
+			carbon_intensity: trade.certificate.carbon_intensity,
+			emissions: trade.emissions(),
+		}
+	}
+}
+
+fn main() -> ExitCode {
+	match run(Cli::parse()) {
+		Ok(()) => ExitCode::SUCCESS,
+		Err(e) => {
+			eprintln!("energy-match: {}", e);
+			ExitCode::FAILURE
+		},
+	}
+}
+
+fn run(cli: Cli) -> Result<(), ReplayError> {
+	let inputs = [&cli.requests, &cli.offers, &cli.grid];
+	if inputs.iter().filter(|path| path.as_path() == Path::new("-")).count() > 1 {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Only one of the requests, offers and grid prices can be read from stdin",
+		)
+		.into());
+	}
+
+	let request_records: Vec<RequestRecord> = read_input(&cli.requests, cli.input_format)?;
+	let offer_records: Vec<OfferRecord> = read_input(&cli.offers, cli.input_format)?;
+	let grid = read_grid(&cli.grid, cli.input_format)?;
+	let grid_request = EnergyRequest {
+		amount: 0,
+		price: grid.request_price,
+		buyer: grid.buyer,
+		..Default::default()
+	};
+	let grid_offer = EnergyOffer {
+		amount: 0,
+		price: grid.offer_price,
+		seller: grid.seller,
+		carbon_intensity: grid.carbon_intensity.unwrap_or_default(),
+		..Default::default()
+	};
+
+	let mut microgrids = BTreeMap::<String, MarketPartition>::new();
+	let mut requests = Vec::with_capacity(request_records.len());
+	let mut offers = Vec::with_capacity(offer_records.len());
+	for r in request_records {
+		let request = EnergyRequest {
+			origin: r.origin()?,
+			amount: r.amount,
+			price: r.price,
+			buyer: r.buyer,
+			budget: r.budget,
+		};
+		microgrid(&mut microgrids, r.microgrid).requests.push(request.clone());
+		requests.push(request);
+	}
+	for o in offer_records {
+		let offer = EnergyOffer {
+			amount: o.amount,
+			price: o.price,
+			seller: o.seller,
+			source: o.source.unwrap_or_default(),
+			carbon_intensity: o.carbon_intensity.unwrap_or_default(),
+		};
+		microgrid(&mut microgrids, o.microgrid).offers.push(offer.clone());
+		offers.push(offer);
+	}
+	let mut microgrids: Vec<MarketPartition> = microgrids.into_values().collect();
+
+	let mode = if cli.low_carbon { MatchingMode::LowCarbon } else { MatchingMode::Price };
+	let (trades, levels): (Vec<Trade>, Vec<Option<ClearingLevel>>) = match cli.strategy {
+		Strategy::Standard => {
+			generate_trades_in_mode(&mut requests, &mut offers, &grid_request, &grid_offer, mode)
+				.into_iter()
+				.map(|t| (t, None))
+				.unzip()
+		},
+		Strategy::Hierarchical => {
+			generate_hierarchical_trades(&mut microgrids, &grid_request, &grid_offer, mode)
+				.into_iter()
+				.map(|t| (t.trade, Some(t.level)))
+				.unzip()
+		},
+		#[cfg(feature = "parallel")]
+		Strategy::Parallel => energy_trade_matching::parallel::generate_trades_parallel(
+			&mut microgrids,
+			&grid_request,
+			&grid_offer,
+			mode,
+		)
+		.into_iter()
+		.map(|t| (t, None))
+		.unzip(),
+	};
+	let records: Vec<TradeRecord> = TradeId::of_round(cli.round, &trades)
+		.into_iter()
+		.zip(&trades)
+		.zip(&levels)
+		.map(|((id, t), l)| TradeRecord::new(id, t, l.as_ref()))
+		.collect();
+	let report = RoundReport::new(&requests, &offers, &trades, &grid_request, &grid_offer);
+
+	write_output(&cli.trades, &records, cli.output_format)?;
+	match &cli.report {
+		Some(path) => write_output(path, &[report], cli.output_format)?,
+		None => eprintln!(
+			"{} trades, {} peer-to-peer, {} from grid, {} to grid, {} unserved, {} g CO2",
+			report.trades,
+			report.peer_to_peer_amount,
+			report.grid_import_amount,
+			report.grid_export_amount,
+			report.unserved_amount,
+			report.total_emissions
+		),
+	}
+
+	Ok(())
+}
+
+fn microgrid(
+	microgrids: &mut BTreeMap<String, MarketPartition>,
+	id: Option<String>,
+) -> &mut MarketPartition {
+	let zone = id.unwrap_or_default();
+	microgrids
+		.entry(zone.clone())
+		.or_insert_with(|| MarketPartition { zone, ..Default::default() })
+}
+
+fn parse_sources(list: &str) -> Result<Vec<EnergySource>, ReplayError> {
+	list.split_whitespace()
+		.map(|source| {
+			EnergySource::deserialize(IntoDeserializer::<value::Error>::into_deserializer(source))
+				.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+		})
+		.collect()
+}
+
+fn format_of(path: &Path, fallback: DumpFormat) -> DumpFormat {
+	DumpFormat::from_path(path).unwrap_or(fallback)
+}
+
+fn open_input(path: &Path) -> Result<Box<dyn Read>, ReplayError> {
+	Ok(if path == Path::new("-") {
+		Box::new(io::stdin().lock())
+	} else {
+		Box::new(File::open(path)?)
+	})
+}
+
+fn read_input<T: DeserializeOwned>(
+	path: &Path,
+	fallback: DumpFormat,
+) -> Result<Vec<T>, ReplayError> {
+	read_dump(open_input(path)?, format_of(path, fallback))
+}
+
+/// Read the grid prices, given as a single record, or in JSON also as a single object.
+fn read_grid(path: &Path, fallback: DumpFormat) -> Result<GridPrices, ReplayError> {
+	let mut records = match format_of(path, fallback) {
+		DumpFormat::Json => match serde_json::from_reader(open_input(path)?)? {
+			OneOrMany::One(grid) => vec![grid],
+			OneOrMany::Many(records) => records,
+		},
+		format => read_dump(open_input(path)?, format)?,
+	};
+	if records.len() != 1 {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("Expected a single record of grid prices, got {}", records.len()),
+		)
+		.into());
+	}
+	Ok(records.remove(0))
+}
+
+fn write_output<T: Serialize>(
+	path: &Path,
+	items: &[T],
+	fallback: DumpFormat,
+) -> Result<(), ReplayError> {
+	let writer: Box<dyn Write> = if path == Path::new("-") {
+		Box::new(io::stdout().lock())
+	} else {
+		Box::new(File::create(path)?)
+	};
+	write_dump(writer, items, format_of(path, fallback))
+}
@@ -0,0 +1,333 @@
+//! SCALE encoding and type information of the matching types, for use in extrinsics and runtime
+//! APIs.
+//!
+//! SCALE has no floating point numbers, so prices, premiums and carbon intensities are encoded as
+//! thousandths in a `u64`, the same way trade ids are derived. Negative values are encoded as zero.
+//! Budgets are encoded the same way.
+
+use std::fmt;
+
+use parity_scale_codec::{Decode, DecodeAll, Encode, Error, Input, MaxEncodedLen, Output};
+use scale_info::{build::Fields, Path, Type, TypeInfo};
+
+use crate::{
+	energy_trade_matching::{
+		Certificate, EnergyOffer, EnergyRequest, EnergySource, SourcePreference, Trade,
+	},
+	trade_id::{participant_bytes, price_in_thousandths, PRICE_SCALE},
+};
+
+const MODULE_PATH: &str = "energy_trade_matching::energy_trade_matching";
+
+fn from_thousandths(thousandths: u64) -> f32 {
+	(thousandths as f64 / PRICE_SCALE) as f32
+}
+
+fn budget_in_thousandths(budget: f64) -> u64 {
+	(budget * PRICE_SCALE).round() as u64
+}
+
+fn budget_from_thousandths(thousandths: u64) -> f64 {
+	thousandths as f64 / PRICE_SCALE
+}
+
+impl Encode for SourcePreference {
+	fn encode_to<O: Output + ?Sized>(&self, dest: &mut O) {
+		(&self.preferred, price_in_thousandths(self.premium), &self.excluded).encode_to(dest)
+	}
+}
+
+impl Decode for SourcePreference {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+		let (preferred, premium, excluded) = Decode::decode(input)?;
+		Ok(SourcePreference { preferred, premium: from_thousandths(premium), excluded })
+	}
+}
+
+impl TypeInfo for SourcePreference {
+	type Identity = Self;
+
+	fn type_info() -> Type {
+		Type::builder().path(Path::new("SourcePreference", MODULE_PATH)).composite(
+			Fields::named()
+				.field(|f| {
+					f.ty::<Vec<EnergySource>>().name("preferred").type_name("Vec<EnergySource>")
+				})
+				.field(|f| f.ty::<u64>().name("premium").type_name("u64"))
+				.field(|f| {
+					f.ty::<Vec<EnergySource>>().name("excluded").type_name("Vec<EnergySource>")
+				}),
+		)
+	}
+}
+
+impl Encode for EnergyRequest {
+	fn encode_to<O: Output + ?Sized>(&self, dest: &mut O) {
+		(
+			self.amount,
+			price_in_thousandths(self.price),
+			&self.buyer,
+			&self.origin,
+			self.budget.map(budget_in_thousandths),
+		)
+			.encode_to(dest)
+	}
+}
+
+impl Decode for EnergyRequest {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+		let (amount, price, buyer, origin, budget): (_, _, _, _, Option<u64>) =
+			Decode::decode(input)?;
+		Ok(EnergyRequest {
+			amount,
+			price: from_thousandths(price),
+			buyer,
+			origin,
+			budget: budget.map(budget_from_thousandths),
+		})
+	}
+}
+
+impl TypeInfo for EnergyRequest {
+	type Identity = Self;
+
+	fn type_info() -> Type {
+		Type::builder().path(Path::new("EnergyRequest", MODULE_PATH)).composite(
+			Fields::named()
+				.field(|f| f.ty::<u16>().name("amount").type_name("u16"))
+				.field(|f| f.ty::<u64>().name("price").type_name("u64"))
+				.field(|f| f.ty::<String>().name("buyer").type_name("String"))
+				.field(|f| f.ty::<SourcePreference>().name("origin").type_name("SourcePreference"))
+				.field(|f| f.ty::<Option<u64>>().name("budget").type_name("Option<u64>")),
+		)
+	}
+}
+
+impl Encode for EnergyOffer {
+	fn encode_to<O: Output + ?Sized>(&self, dest: &mut O) {
+		(
+			self.amount,
+			price_in_thousandths(self.price),
+			&self.seller,
+			self.source,
+			price_in_thousandths(self.carbon_intensity),
+		)
+			.encode_to(dest)
+	}
+}
+
+impl Decode for EnergyOffer {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+		let (amount, price, seller, source, carbon_intensity) = Decode::decode(input)?;
+		Ok(EnergyOffer {
+			amount,
+			price: from_thousandths(price),
+			seller,
+			source,
+			carbon_intensity: from_thousandths(carbon_intensity),
+		})
+	}
+}
+
+impl TypeInfo for EnergyOffer {
+	type Identity = Self;
+
+	fn type_info() -> Type {
+		Type::builder().path(Path::new("EnergyOffer", MODULE_PATH)).composite(
+			Fields::named()
+				.field(|f| f.ty::<u16>().name("amount").type_name("u16"))
+				.field(|f| f.ty::<u64>().name("price").type_name("u64"))
+				.field(|f| f.ty::<String>().name("seller").type_name("String"))
+				.field(|f| f.ty::<EnergySource>().name("source").type_name("EnergySource"))
+				.field(|f| f.ty::<u64>().name("carbon_intensity").type_name("u64")),
+		)
+	}
+}
+
+impl Encode for Certificate {
+	fn encode_to<O: Output + ?Sized>(&self, dest: &mut O) {
+		(
+			self.source,
+			price_in_thousandths(self.premium),
+			price_in_thousandths(self.carbon_intensity),
+		)
+			.encode_to(dest)
+	}
+}
+
+impl Decode for Certificate {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+		let (source, premium, carbon_intensity) = Decode::decode(input)?;
+		Ok(Certificate {
+			source,
+			premium: from_thousandths(premium),
+			carbon_intensity: from_thousandths(carbon_intensity),
+		})
+	}
+}
+
+impl MaxEncodedLen for Certificate {
+	fn max_encoded_len() -> usize {
+		EnergySource::max_encoded_len() + 2 * u64::max_encoded_len()
+	}
+}
+
+impl TypeInfo for Certificate {
+	type Identity = Self;
+
+	fn type_info() -> Type {
+		Type::builder().path(Path::new("Certificate", MODULE_PATH)).composite(
+			Fields::named()
+				.field(|f| f.ty::<EnergySource>().name("source").type_name("EnergySource"))
+				.field(|f| f.ty::<u64>().name("premium").type_name("u64"))
+				.field(|f| f.ty::<u64>().name("carbon_intensity").type_name("u64")),
+		)
+	}
+}
+
+impl Encode for Trade {
+	fn encode_to<O: Output + ?Sized>(&self, dest: &mut O) {
+		(self.amount, price_in_thousandths(self.price), &self.buyer, &self.seller, self.certificate)
+			.encode_to(dest)
+	}
+}
+
+impl Decode for Trade {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+		let (amount, price, buyer, seller, certificate) = Decode::decode(input)?;
+		Ok(Trade { amount, price: from_thousandths(price), buyer, seller, certificate })
+	}
+}
+
+impl TypeInfo for Trade {
+	type Identity = Self;
+
+	fn type_info() -> Type {
+		Type::builder().path(Path::new("Trade", MODULE_PATH)).composite(
+			Fields::named()
+				.field(|f| f.ty::<u16>().name("amount").type_name("u16"))
+				.field(|f| f.ty::<u64>().name("price").type_name("u64"))
+				.field(|f| f.ty::<String>().name("buyer").type_name("String"))
+				.field(|f| f.ty::<String>().name("seller").type_name("String"))
+				.field(|f| f.ty::<Certificate>().name("certificate").type_name("Certificate")),
+		)
+	}
+}
+
+/// A trade as `pallet_mediator::Trade<T>` stores it, in a runtime with the given account id.
+///
+/// Both encode the same, so one can be decoded as the other.
+#[derive(Encode, Decode, TypeInfo, MaxEncodedLen, Debug, Clone, PartialEq, Eq)]
+pub struct ChainTrade<AccountId> {
+	pub amount: u16,
+	pub price: u8,
+	pub seller: AccountId,
+	pub buyer: AccountId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConversionError {
+	/// The price is not a whole number the pallet can store
+	Price(f32),
+	/// The participant id is not an account id in `0x` prefixed hex
+	Account(String),
+}
+
+impl fmt::Display for ConversionError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ConversionError::Price(price) =>
+				write!(f, "Price {} can not be stored on chain", price),
+			ConversionError::Account(id) => write!(f, "{} is not an account id", id),
+		}
+	}
+}
+
+impl std::error::Error for ConversionError {}
+
+impl<AccountId: Decode> TryFrom<&Trade> for ChainTrade<AccountId> {
+	type Error = ConversionError;
+
+	fn try_from(trade: &Trade) -> Result<Self, Self::Error> {
+		let account = |id: &String| {
+			AccountId::decode_all(&mut participant_bytes(id).as_slice())
+				.map_err(|_| ConversionError::Account(id.clone()))
+		};
+		if trade.price.fract() != 0.0 || !(0.0..=u8::MAX as f32).contains(&trade.price) {
+			return Err(ConversionError::Price(trade.price))
+		}
+
+		Ok(ChainTrade {
+			amount: trade.amount,
+			price: trade.price as u8,
+			seller: account(&trade.seller)?,
+			buyer: account(&trade.buyer)?,
+		})
+	}
+}
+
+impl<AccountId: Encode> From<&ChainTrade<AccountId>> for Trade {
+	/// The participants are identified by their SCALE encoded account ids in `0x` prefixed hex.
+	fn from(trade: &ChainTrade<AccountId>) -> Self {
+		Trade {
+			amount: trade.amount,
+			price: trade.price as f32,
+			buyer: format!("0x{}", hex::encode(trade.buyer.encode())),
+			seller: format!("0x{}", hex::encode(trade.seller.encode())),
+			..Default::default()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::trade_id::TradeId;
+
+	#[test]
+	fn orders_round_trip() {
+		let request = EnergyRequest {
+			amount: 10,
+			price: 2.35,
+			buyer: "buyer_1".into(),
+			origin: SourcePreference {
+				preferred: vec![EnergySource::Pv],
+				premium: 0.5,
+				excluded: vec![EnergySource::Chp],
+			},
+			budget: Some(25.5),
+		};
+
+		let decoded = EnergyRequest::decode(&mut request.encode().as_slice()).unwrap();
+
+		assert_eq!(
+			serde_json::to_value(&decoded).unwrap(),
+			serde_json::to_value(&request).unwrap()
+		);
+	}
+
+	#[test]
+	fn trades_convert_to_and_from_the_pallet_encoding() {
+		let trade = Trade {
+			amount: 10,
+			price: 3.0,
+			buyer: "0x0200000000000000".into(),
+			seller: "0x0100000000000000".into(),
+			..Default::default()
+		};
+
+		let on_chain = ChainTrade::<u64>::try_from(&trade).unwrap();
+
+		assert_eq!(on_chain, ChainTrade { amount: 10, price: 3, seller: 1, buyer: 2 });
+		assert_eq!(on_chain.encode(), (10u16, 3u8, 1u64, 2u64).encode());
+		assert_eq!(TradeId::of(7, 0, &Trade::from(&on_chain)), TradeId::of(7, 0, &trade));
+		assert_eq!(
+			ChainTrade::<u64>::try_from(&Trade { price: 2.35, ..trade.clone() }),
+			Err(ConversionError::Price(2.35))
+		);
+		assert_eq!(
+			ChainTrade::<u64>::try_from(&Trade { buyer: "buyer_1".into(), ..trade }),
+			Err(ConversionError::Account("buyer_1".into()))
+		);
+	}
+}
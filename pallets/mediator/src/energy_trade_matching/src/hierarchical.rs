@@ -0,0 +1,207 @@
+//! Hierarchical clearing following the structure of PolkEM.
+//!
+//! Orders are first matched within their own microgrid. The residuals of all microgrids are then
+//! matched with each other on the mediator, and only what is left after that is traded with the
+//! grid.
+
+use serde::{Deserialize, Serialize};
+
+use crate::energy_trade_matching::{
+	clear_peer_to_peer_in_mode, clear_residuals, EnergyOffer, EnergyRequest, MarketPartition,
+	MatchingMode, PeerToPeerClearing, Trade,
+};
+
+/// The level of the market hierarchy at which a trade was cleared
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ClearingLevel {
+	/// Both parties belong to the microgrid with the given id
+	IntraMicrogrid(String),
+	/// The parties belong to different microgrids and were matched on the mediator
+	InterMicrogrid,
+	/// One of the parties is the grid
+	Grid,
+}
+
+/// A trade along with the level at which it was cleared
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HierarchicalTrade {
+	pub level: ClearingLevel,
+	#[serde(flatten)]
+	pub trade: Trade,
+}
+
+/// Clear each microgrid locally, then the residuals across microgrids, then the rest with the grid,
+/// breaking ties between equally priced offers at every level as the mode says.
+///
+/// The `zone` of each partition is used as the microgrid id.
+pub fn generate_hierarchical_trades(
+	microgrids: &mut [MarketPartition],
+	grid_request: &EnergyRequest,
+	grid_offer: &EnergyOffer,
+	mode: MatchingMode,
+) -> Vec<HierarchicalTrade> {
+	let mut trades = Vec::<HierarchicalTrade>::new();
+
+	// Intra-microgrid
+	let cleared: Vec<PeerToPeerClearing> = microgrids
+		.iter_mut()
+		.map(|microgrid| {
+			let clearing =
+				clear_peer_to_peer_in_mode(&mut microgrid.requests, &mut microgrid.offers, mode);
+			trades.extend(clearing.trades.iter().map(|trade| HierarchicalTrade {
+				level: ClearingLevel::IntraMicrogrid(microgrid.zone.clone()),
+				trade: trade.clone(),
+			}));
+			clearing
+		})
+		.collect();
+
+	// Inter-microgrid and grid
+	let residual = clear_residuals(&cleared, grid_request, grid_offer, mode);
+	let level =
+		|level: ClearingLevel| move |trade| HierarchicalTrade { level: level.clone(), trade };
+	trades.extend(residual.trades.into_iter().map(level(ClearingLevel::InterMicrogrid)));
+	trades.extend(residual.grid_trades.into_iter().map(level(ClearingLevel::Grid)));
+
+	trades
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn grid() -> (EnergyRequest, EnergyOffer) {
+		(
+			EnergyRequest { amount: 10, price: 1.5, buyer: "grid".into(), ..Default::default() },
+			EnergyOffer { amount: 10, price: 3.0, seller: "grid".into(), ..Default::default() },
+		)
+	}
+
+	#[test]
+	fn local_orders_are_matched_before_other_microgrids_and_the_grid() {
+		let mut microgrids = vec![
+			MarketPartition {
+				zone: "mg_1".into(),
+				requests: vec![EnergyRequest {
+					amount: 10,
+					price: 2.5,
+					buyer: "buyer_1".into(),
+					..Default::default()
+				}],
+				offers: vec![EnergyOffer {
+					amount: 5,
+					price: 2.4,
+					seller: "seller_1".into(),
+					..Default::default()
+				}],
+			},
+			MarketPartition {
+				zone: "mg_2".into(),
+				requests: vec![EnergyRequest {
+					amount: 10,
+					price: 2.0,
+					buyer: "buyer_2".into(),
+					..Default::default()
+				}],
+				offers: vec![EnergyOffer {
+					amount: 5,
+					price: 2.1,
+					seller: "seller_2".into(),
+					..Default::default()
+				}],
+			},
+		];
+		let (grid_request, grid_offer) = grid();
+
+		let trades = generate_hierarchical_trades(
+			&mut microgrids,
+			&grid_request,
+			&grid_offer,
+			MatchingMode::Price,
+		);
+
+		// buyer_1 gets 5 from seller_1 locally, 5 from seller_2 in mg_2, buyer_2 has to use the grid
+		assert_eq!(trades.len(), 3);
+		assert_eq!(trades[0].level, ClearingLevel::IntraMicrogrid("mg_1".into()));
+		assert_eq!(trades[0].trade.seller, "seller_1");
+		assert_eq!(trades[1].level, ClearingLevel::InterMicrogrid);
+		assert_eq!(trades[1].trade.buyer, "buyer_1");
+		assert_eq!(trades[1].trade.seller, "seller_2");
+		assert_eq!(trades[2].level, ClearingLevel::Grid);
+		assert_eq!(trades[2].trade.buyer, "buyer_2");
+	}
+
+	#[test]
+	fn only_grid_trades_involve_the_grid() {
+		let mut microgrids = vec![MarketPartition {
+			zone: "mg_1".into(),
+			requests: vec![EnergyRequest {
+				amount: 10,
+				price: 1.9,
+				buyer: "buyer_1".into(),
+				..Default::default()
+			}],
+			offers: vec![EnergyOffer {
+				amount: 10,
+				price: 2.0,
+				seller: "seller_1".into(),
+				..Default::default()
+			}],
+		}];
+		let (grid_request, grid_offer) = grid();
+
+		let trades = generate_hierarchical_trades(
+			&mut microgrids,
+			&grid_request,
+			&grid_offer,
+			MatchingMode::Price,
+		);
+
+		assert_eq!(trades.len(), 2);
+		assert!(trades.iter().all(|t| t.level == ClearingLevel::Grid));
+		assert!(trades.iter().all(|t| t.trade.buyer == "grid" || t.trade.seller == "grid"));
+	}
+
+	#[test]
+	fn residuals_are_matched_in_the_given_mode() {
+		let offer = |seller: &str, carbon_intensity| EnergyOffer {
+			amount: 10,
+			price: 2.0,
+			seller: seller.into(),
+			carbon_intensity,
+			..Default::default()
+		};
+		let microgrids = vec![
+			MarketPartition {
+				zone: "mg_1".into(),
+				requests: vec![EnergyRequest {
+					amount: 10,
+					price: 2.5,
+					buyer: "buyer_1".into(),
+					..Default::default()
+				}],
+				offers: vec![],
+			},
+			MarketPartition {
+				zone: "mg_2".into(),
+				requests: vec![],
+				offers: vec![offer("seller_1", 400.0), offer("seller_2", 20.0)],
+			},
+		];
+		let (grid_request, grid_offer) = grid();
+
+		for (mode, seller) in
+			[(MatchingMode::Price, "seller_1"), (MatchingMode::LowCarbon, "seller_2")]
+		{
+			let trades = generate_hierarchical_trades(
+				&mut microgrids.clone(),
+				&grid_request,
+				&grid_offer,
+				mode,
+			);
+
+			assert_eq!(trades[0].level, ClearingLevel::InterMicrogrid);
+			assert_eq!(trades[0].trade.seller, seller);
+		}
+	}
+}
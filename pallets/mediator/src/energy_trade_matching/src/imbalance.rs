@@ -0,0 +1,254 @@
+//! Imbalance settlement of traded positions against metered delivery.
+//!
+//! Trades are commitments based on forecasts. Once meter data is known, the deviation of each
+//! participant from their traded position is priced at imbalance prices and corrected with trades
+//! against the grid.
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	energy_trade_matching::{Certificate, EnergyOffer, EnergyRequest, Trade},
+	settlement::settle,
+};
+
+/// Energy a participant actually consumed from and injected into the grid during a round
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MeterReading {
+	pub participant: String,
+	pub consumed: u64,
+	pub injected: u64,
+}
+
+/// Prices at which deviations from traded positions are settled
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImbalancePrices {
+	/// Paid by a participant for every unit consumed beyond their position
+	pub shortfall_price: f32,
+	/// Paid to a participant for every unit injected beyond their position
+	pub surplus_price: f32,
+}
+
+/// Deviation of a single participant from their traded position
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Imbalance {
+	pub participant: String,
+	/// Energy bought minus energy sold
+	pub traded_net: i64,
+	/// Energy consumed minus energy injected
+	pub metered_net: i64,
+	/// Metered minus traded, positive when the participant was short
+	pub deviation: i64,
+	/// Money owed by the participant for the deviation, negative when they are paid
+	pub cost: f64,
+}
+
+/// Why the imbalances of a round can not be settled
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImbalanceError {
+	/// The net metered energy of the participant, or its deviation, does not fit an `i64`
+	Overflow { participant: String },
+}
+
+impl fmt::Display for ImbalanceError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ImbalanceError::Overflow { participant } => {
+				write!(f, "Metered energy of {} is out of range", participant)
+			},
+		}
+	}
+}
+
+impl std::error::Error for ImbalanceError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ImbalanceSettlement {
+	pub imbalances: Vec<Imbalance>,
+	/// Trades with the grid making up for the deviations
+	pub correction_trades: Vec<Trade>,
+}
+
+/// Compare the traded positions with the meter readings, ordered by participant.
+///
+/// Participants without a reading are taken to have consumed and injected nothing, and
+/// participants without trades to have a traded position of zero. Fails if the net metered
+/// energy of a participant, or its deviation, is out of the range of an `i64`.
+pub fn settle_imbalances(
+	trades: &[Trade],
+	readings: &[MeterReading],
+	grid_request: &EnergyRequest,
+	grid_offer: &EnergyOffer,
+	prices: &ImbalancePrices,
+) -> Result<ImbalanceSettlement, ImbalanceError> {
+	let mut positions = BTreeMap::<&str, (i64, i128)>::new();
+	let statements = settle(trades, grid_request, grid_offer);
+	for statement in &statements {
+		positions.entry(&statement.participant).or_default().0 = statement.net_energy;
+	}
+	for reading in readings {
+		positions.entry(&reading.participant).or_default().1 +=
+			reading.consumed as i128 - reading.injected as i128;
+	}
+
+	let mut settlement = ImbalanceSettlement::default();
+	for (participant, (traded_net, metered_net)) in positions {
+		let overflow = || ImbalanceError::Overflow { participant: participant.to_owned() };
+		let metered_net = i64::try_from(metered_net).map_err(|_| overflow())?;
+		let deviation = metered_net.checked_sub(traded_net).ok_or_else(overflow)?;
+		if deviation == 0 {
+			continue
+		}
+
+		let (price, cost) = if deviation > 0 {
+			(prices.shortfall_price, deviation as f64 * prices.shortfall_price as f64)
+		} else {
+			(prices.surplus_price, deviation as f64 * prices.surplus_price as f64)
+		};
+
+		let mut remaining = deviation.unsigned_abs();
+		while remaining > 0 {
+			let amount = remaining.min(u16::MAX as u64) as u16;
+			remaining -= amount as u64;
+			settlement.correction_trades.push(if deviation > 0 {
+				Trade {
+					amount,
+					price,
+					buyer: participant.to_owned(),
+					seller: grid_offer.seller.clone(),
+					certificate: Certificate {
+						source: grid_offer.source,
+						premium: 0.0,
+						carbon_intensity: grid_offer.carbon_intensity,
+					},
+				}
+			} else {
+				Trade {
+					amount,
+					price,
+					buyer: grid_request.buyer.clone(),
+					seller: participant.to_owned(),
+					..Default::default()
+				}
+			});
+		}
+
+		settlement.imbalances.push(Imbalance {
+			participant: participant.to_owned(),
+			traded_net,
+			metered_net,
+			deviation,
+			cost,
+		});
+	}
+
+	Ok(settlement)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn reading(participant: &str, consumed: u64, injected: u64) -> MeterReading {
+		MeterReading { participant: participant.into(), consumed, injected }
+	}
+
+	#[test]
+	fn deviations_are_priced_and_corrected_with_the_grid() {
+		let grid_request = EnergyRequest {
+			amount: 10,
+			price: 1.5,
+			buyer: "grid".into(),
+			..Default::default()
+		};
+		let grid_offer = EnergyOffer {
+			amount: 10,
+			price: 3.0,
+			seller: "grid".into(),
+			..Default::default()
+		};
+		let prices = ImbalancePrices { shortfall_price: 4.0, surplus_price: 1.0 };
+		let trades = vec![
+			Trade {
+				amount: 10,
+				price: 2.0,
+				buyer: "consumer_1".into(),
+				seller: "producer_1".into(),
+				..Default::default()
+			},
+			Trade {
+				amount: 5,
+				price: 3.0,
+				buyer: "consumer_2".into(),
+				seller: "grid".into(),
+				..Default::default()
+			},
+		];
+		let readings = vec![
+			reading("consumer_1", 12, 0),
+			reading("consumer_2", 5, 0),
+			reading("producer_1", 0, 7),
+		];
+
+		let settlement =
+			settle_imbalances(&trades, &readings, &grid_request, &grid_offer, &prices).unwrap();
+
+		// consumer_2 delivered as traded, consumer_1 was short by 2 and producer_1 by 3
+		assert_eq!(settlement.imbalances.len(), 2);
+		assert_eq!(settlement.imbalances[0].participant, "consumer_1");
+		assert_eq!(settlement.imbalances[0].deviation, 2);
+		assert_eq!(settlement.imbalances[0].cost, 8.0);
+		assert_eq!(settlement.imbalances[1].participant, "producer_1");
+		assert_eq!(settlement.imbalances[1].deviation, 3);
+		assert_eq!(settlement.imbalances[1].cost, 12.0);
+		assert!(settlement.correction_trades.iter().all(|t| t.seller == "grid"));
+		assert_eq!(settlement.correction_trades.iter().map(|t| t.amount as u64).sum::<u64>(), 5);
+	}
+
+	#[test]
+	fn surplus_is_sold_to_the_grid() {
+		let grid_request = EnergyRequest {
+			amount: 10,
+			price: 1.5,
+			buyer: "grid".into(),
+			..Default::default()
+		};
+		let grid_offer = EnergyOffer {
+			amount: 10,
+			price: 3.0,
+			seller: "grid".into(),
+			..Default::default()
+		};
+		let prices = ImbalancePrices { shortfall_price: 4.0, surplus_price: 1.0 };
+
+		let readings = vec![reading("producer_1", 0, 6)];
+
+		let settlement =
+			settle_imbalances(&[], &readings, &grid_request, &grid_offer, &prices).unwrap();
+
+		assert_eq!(settlement.imbalances[0].deviation, -6);
+		assert_eq!(settlement.imbalances[0].cost, -6.0);
+		let correction = &settlement.correction_trades[0];
+		assert_eq!((correction.amount, correction.price), (6, 1.0));
+		assert_eq!((correction.buyer.as_str(), correction.seller.as_str()), ("grid", "producer_1"));
+	}
+
+	#[test]
+	fn readings_beyond_i64_are_rejected() {
+		let grid_request = EnergyRequest { buyer: "grid".into(), ..Default::default() };
+		let grid_offer = EnergyOffer { seller: "grid".into(), ..Default::default() };
+		let prices = ImbalancePrices { shortfall_price: 4.0, surplus_price: 1.0 };
+		let overflow = Err(ImbalanceError::Overflow { participant: "consumer_1".into() });
+
+		let readings = vec![reading("consumer_1", u64::MAX, 0)];
+		let settlement = settle_imbalances(&[], &readings, &grid_request, &grid_offer, &prices);
+		assert_eq!(settlement.map(|s| s.imbalances), overflow);
+
+		// Readings out of range on their own are fine as long as the net energy is not
+		let readings = vec![reading("consumer_1", u64::MAX, u64::MAX - 2)];
+		let settlement =
+			settle_imbalances(&[], &readings, &grid_request, &grid_offer, &prices).unwrap();
+		assert_eq!(settlement.imbalances[0].metered_net, 2);
+	}
+}
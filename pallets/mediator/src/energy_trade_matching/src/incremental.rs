@@ -0,0 +1,442 @@
+//! Incremental clearing of a round whose orders change after matching.
+//!
+//! Requests are served one after another in ascending order of price, so a changed order only
+//! affects the requests served from the first one that could trade with it on. The books are kept
+//! sorted along with the trades of every request, and only those requests are matched again.
+
+use std::{
+	cmp::Ordering,
+	collections::{BTreeMap, BTreeSet, HashMap},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	energy_trade_matching::{
+		clear_with_grid, EnergyOffer, EnergyRequest, MatchingMode, OfferQueues, Trade,
+	},
+	replay::diff_trades,
+};
+
+/// An order, by the position it was given or added in among the requests or offers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OrderId {
+	Request(u64),
+	Offer(u64),
+}
+
+/// Trades to withdraw and trades to add after orders changed
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TradeDelta {
+	pub cancelled: Vec<Trade>,
+	pub created: Vec<Trade>,
+}
+
+impl TradeDelta {
+	pub fn is_empty(&self) -> bool {
+		self.cancelled.is_empty() && self.created.is_empty()
+	}
+}
+
+/// Price or carbon intensity, totally ordered to key the books with
+#[derive(Debug, Clone, Copy)]
+struct Key(f32);
+
+impl PartialEq for Key {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Key {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self.0.total_cmp(&other.0)
+	}
+}
+
+/// Price and id of a request
+type RequestKey = (Key, u64);
+/// Price, carbon intensity if it breaks ties, and id of an offer
+type OfferKey = (Key, Key, u64);
+
+struct RequestEntry {
+	request: EnergyRequest,
+	/// Trades with peers, along with the ids of their offers
+	fills: Vec<(u64, Trade)>,
+	/// Amount left for the grid
+	unmatched: u16,
+}
+
+struct OfferEntry {
+	offer: EnergyOffer,
+	/// Amount left for the grid
+	left: u16,
+}
+
+/// Sorted books of a round along with the trades formed from them.
+///
+/// The trades are the same as `generate_trades_in_mode` forms from the orders in the order of
+/// their ids, though not in the same order.
+pub struct IncrementalClearing {
+	grid_request: EnergyRequest,
+	grid_offer: EnergyOffer,
+	mode: MatchingMode,
+	requests: BTreeMap<RequestKey, RequestEntry>,
+	offers: BTreeMap<OfferKey, OfferEntry>,
+	request_keys: HashMap<u64, RequestKey>,
+	offer_keys: HashMap<u64, OfferKey>,
+	next_request: u64,
+	next_offer: u64,
+}
+
+impl IncrementalClearing {
+	/// Clear the orders, numbering requests and offers in the order given.
+	pub fn new(
+		requests: Vec<EnergyRequest>,
+		offers: Vec<EnergyOffer>,
+		grid_request: &EnergyRequest,
+		grid_offer: &EnergyOffer,
+		mode: MatchingMode,
+	) -> Self {
+		let mut clearing = IncrementalClearing {
+			grid_request: grid_request.clone(),
+			grid_offer: grid_offer.clone(),
+			mode,
+			requests: BTreeMap::new(),
+			offers: BTreeMap::new(),
+			request_keys: HashMap::new(),
+			offer_keys: HashMap::new(),
+			next_request: 0,
+			next_offer: 0,
+		};
+		for request in requests {
+			clearing.insert_request(request);
+		}
+		for offer in offers {
+			clearing.insert_offer(offer);
+		}
+
+		if let Some(&first) = clearing.requests.keys().next() {
+			for (offer, amount) in clearing.match_from(first) {
+				clearing.offers.get_mut(&clearing.offer_keys[&offer]).unwrap().left -= amount;
+			}
+		}
+		clearing
+	}
+
+	/// All current trades.
+	pub fn trades(&self) -> Vec<Trade> {
+		let mut trades: Vec<Trade> =
+			self.requests.values().flat_map(|entry| self.request_trades(entry)).collect();
+		trades.extend(self.offer_keys.keys().flat_map(|&id| self.offer_trades(id)));
+		trades
+	}
+
+	pub fn add_request(&mut self, request: EnergyRequest) -> (OrderId, TradeDelta) {
+		let key = (Key(request.price), self.next_request);
+		let delta = self.rematch(Some(key), None, |clearing| {
+			clearing.insert_request(request);
+		});
+		(OrderId::Request(key.1), delta)
+	}
+
+	pub fn add_offer(&mut self, offer: EnergyOffer) -> (OrderId, TradeDelta) {
+		let id = self.next_offer;
+		let first = self.first_request_accepting(&offer);
+		let delta = self.rematch(first, Some(id), |clearing| {
+			clearing.insert_offer(offer);
+		});
+		(OrderId::Offer(id), delta)
+	}
+
+	/// Withdraw the order, or return `None` if there is no such order.
+	pub fn remove(&mut self, id: OrderId) -> Option<TradeDelta> {
+		match id {
+			OrderId::Request(id) => {
+				let key = *self.request_keys.get(&id)?;
+				Some(self.rematch(Some(key), None, |clearing| {
+					clearing.request_keys.remove(&id);
+					clearing.requests.remove(&key);
+				}))
+			},
+			OrderId::Offer(id) => {
+				let key = *self.offer_keys.get(&id)?;
+				let first = self.first_request_accepting(&self.offers[&key].offer);
+				Some(self.rematch(first, Some(id), |clearing| {
+					clearing.offer_keys.remove(&id);
+					clearing.offers.remove(&key);
+				}))
+			},
+		}
+	}
+
+	/// Replace the request, or return `None` if there is no such request.
+	pub fn amend_request(&mut self, id: OrderId, request: EnergyRequest) -> Option<TradeDelta> {
+		let OrderId::Request(id) = id else { return None };
+		let old_key = *self.request_keys.get(&id)?;
+		let new_key = (Key(request.price), id);
+
+		Some(self.rematch(Some(old_key.min(new_key)), None, |clearing| {
+			clearing.requests.remove(&old_key);
+			clearing.requests.insert(new_key, RequestEntry::new(request));
+			clearing.request_keys.insert(id, new_key);
+		}))
+	}
+
+	/// Replace the offer, or return `None` if there is no such offer.
+	pub fn amend_offer(&mut self, id: OrderId, offer: EnergyOffer) -> Option<TradeDelta> {
+		let OrderId::Offer(id) = id else { return None };
+		let old_key = *self.offer_keys.get(&id)?;
+		let new_key = self.offer_key(&offer, id);
+		let first = [
+			self.first_request_accepting(&self.offers[&old_key].offer),
+			self.first_request_accepting(&offer),
+		]
+		.into_iter()
+		.flatten()
+		.min();
+
+		Some(self.rematch(first, Some(id), |clearing| {
+			clearing.offers.remove(&old_key);
+			clearing.offers.insert(new_key, OfferEntry::new(offer));
+			clearing.offer_keys.insert(id, new_key);
+		}))
+	}
+
+	fn insert_request(&mut self, request: EnergyRequest) {
+		let key = (Key(request.price), self.next_request);
+		self.next_request += 1;
+		self.request_keys.insert(key.1, key);
+		self.requests.insert(key, RequestEntry::new(request));
+	}
+
+	fn insert_offer(&mut self, offer: EnergyOffer) {
+		let key = self.offer_key(&offer, self.next_offer);
+		self.next_offer += 1;
+		self.offer_keys.insert(key.2, key);
+		self.offers.insert(key, OfferEntry::new(offer));
+	}
+
+	/// Key sorting offers the way `clear_peer_to_peer_in_mode` does
+	fn offer_key(&self, offer: &EnergyOffer, id: u64) -> OfferKey {
+		match self.mode {
+			MatchingMode::Price => (Key(offer.price), Key(0.0), id),
+			MatchingMode::LowCarbon => (Key(offer.price), Key(offer.carbon_intensity), id),
+		}
+	}
+
+	/// Requests served before this one could not buy from the offer, whatever it is offering
+	fn first_request_accepting(&self, offer: &EnergyOffer) -> Option<RequestKey> {
+		self.requests.iter().find(|(_, entry)| entry.request.accepts(offer)).map(|(key, _)| *key)
+	}
+
+	/// Apply the change and match the requests from the given one on again, along with the offers
+	/// left by the requests before it.
+	fn rematch(
+		&mut self,
+		first: Option<RequestKey>,
+		changed_offer: Option<u64>,
+		change: impl FnOnce(&mut Self),
+	) -> TradeDelta {
+		let mut old_trades = Vec::new();
+		let mut touched = BTreeSet::from_iter(changed_offer);
+		if let Some(first) = first {
+			for entry in self.requests.range(first..).map(|(_, entry)| entry) {
+				old_trades.extend(self.request_trades(entry));
+				touched.extend(entry.fills.iter().map(|(offer, _)| *offer));
+			}
+		}
+		old_trades.extend(touched.iter().flat_map(|&id| self.offer_trades(id)));
+
+		// Put back what the affected requests bought, as if only the requests before were served
+		if let Some(first) = first {
+			for entry in self.requests.range(first..).map(|(_, entry)| entry) {
+				for (offer, trade) in &entry.fills {
+					self.offers.get_mut(&self.offer_keys[offer]).unwrap().left += trade.amount;
+				}
+			}
+		}
+
+		change(self);
+
+		let mut new_trades = Vec::new();
+		if let Some(first) = first {
+			for (offer, amount) in self.match_from(first) {
+				// Offers not bought from before are still left as they were
+				if touched.insert(offer) {
+					old_trades.extend(self.offer_trades(offer));
+				}
+				self.offers.get_mut(&self.offer_keys[&offer]).unwrap().left -= amount;
+			}
+			for entry in self.requests.range(first..).map(|(_, entry)| entry) {
+				new_trades.extend(self.request_trades(entry));
+			}
+		}
+		new_trades.extend(touched.iter().flat_map(|&id| self.offer_trades(id)));
+
+		let diff = diff_trades(&old_trades, &new_trades);
+		TradeDelta { cancelled: diff.missing, created: diff.unexpected }
+	}
+
+	/// Match the requests from the given one on with what is left of the offers, returning the
+	/// amount bought of each offer.
+	fn match_from(&mut self, first: RequestKey) -> HashMap<u64, u16> {
+		let mut offer_queues = OfferQueues::new(
+			self.offers.iter().map(|(key, entry)| (key.2, &entry.offer, entry.left)),
+			self.mode,
+		);
+		let mut bought = HashMap::<u64, u16>::new();
+		for entry in self.requests.range_mut(first..).map(|(_, entry)| entry) {
+			let RequestEntry { request, fills, unmatched } = entry;
+			fills.clear();
+			*unmatched = offer_queues.match_request(request, |offer, trade| {
+				*bought.entry(offer).or_default() += trade.amount;
+				fills.push((offer, trade));
+			});
+		}
+		bought
+	}
+
+	fn request_trades(&self, entry: &RequestEntry) -> Vec<Trade> {
+		let mut trades: Vec<Trade> = entry.fills.iter().map(|(_, trade)| trade.clone()).collect();
+		if entry.unmatched > 0 {
+			let spent = trades.iter().map(Trade::cost).sum();
+			let unmatched = entry.request.remainder(entry.unmatched, spent);
+			trades.extend(clear_with_grid(&[unmatched], &[], &self.grid_request, &self.grid_offer));
+		}
+		trades
+	}
+
+	fn offer_trades(&self, id: u64) -> Vec<Trade> {
+		match self.offer_keys.get(&id).map(|key| &self.offers[key]) {
+			Some(entry) if entry.left > 0 => {
+				let left = EnergyOffer { amount: entry.left, ..entry.offer.clone() };
+				clear_with_grid(&[], &[left], &self.grid_request, &self.grid_offer)
+			},
+			_ => Vec::new(),
+		}
+	}
+}
+
+impl RequestEntry {
+	fn new(request: EnergyRequest) -> Self {
+		RequestEntry { unmatched: request.amount, request, fills: Vec::new() }
+	}
+}
+
+impl OfferEntry {
+	fn new(offer: EnergyOffer) -> Self {
+		OfferEntry { left: offer.amount, offer }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		energy_trade_matching::generate_trades,
+		scenario::{Scenario, ScenarioConfig},
+	};
+
+	fn apply(mut trades: Vec<Trade>, delta: &TradeDelta) -> Vec<Trade> {
+		for cancelled in &delta.cancelled {
+			let position = trades
+				.iter()
+				.position(|t| {
+					(t.amount, t.price, &t.buyer, &t.seller) ==
+						(cancelled.amount, cancelled.price, &cancelled.buyer, &cancelled.seller)
+				})
+				.expect("Only existing trades are cancelled");
+			trades.swap_remove(position);
+		}
+		trades.extend(delta.created.iter().cloned());
+		trades
+	}
+
+	#[test]
+	fn changes_match_clearing_from_scratch() {
+		let config = ScenarioConfig { seed: 3, consumers: 40, producers: 40, ..Default::default() };
+		let scenario = Scenario::generate(&config).unwrap();
+		let (requests, offers) = scenario.orders();
+		let (grid_request, grid_offer) = (&scenario.grid_request, &scenario.grid_offer);
+		let mut current_requests: Vec<Option<EnergyRequest>> =
+			requests.iter().cloned().map(Some).collect();
+		let mut current_offers: Vec<Option<EnergyOffer>> =
+			offers.iter().cloned().map(Some).collect();
+
+		let mode = MatchingMode::Price;
+		let mut clearing =
+			IncrementalClearing::new(requests, offers, grid_request, grid_offer, mode);
+		let mut trades = clearing.trades();
+
+		let cheap_offer =
+			EnergyOffer { amount: 300, price: 1.0, ..current_offers[0].clone().unwrap() };
+		let expensive_request =
+			EnergyRequest { amount: 500, price: 3.9, ..current_requests[0].clone().unwrap() };
+		let deltas = vec![
+			clearing.remove(OrderId::Offer(5)).unwrap(),
+			clearing.remove(OrderId::Request(7)).unwrap(),
+			clearing.amend_offer(OrderId::Offer(0), cheap_offer.clone()).unwrap(),
+			clearing.amend_request(OrderId::Request(0), expensive_request.clone()).unwrap(),
+			clearing.add_offer(EnergyOffer { price: 2.5, ..cheap_offer.clone() }).1,
+			clearing.add_request(EnergyRequest { price: 2.1, ..expensive_request.clone() }).1,
+		];
+		current_offers[5] = None;
+		current_requests[7] = None;
+		current_offers[0] = Some(cheap_offer.clone());
+		current_requests[0] = Some(expensive_request.clone());
+		current_offers.push(Some(EnergyOffer { price: 2.5, ..cheap_offer }));
+		current_requests.push(Some(EnergyRequest { price: 2.1, ..expensive_request }));
+
+		for delta in &deltas {
+			assert!(!delta.is_empty());
+			trades = apply(trades, delta);
+		}
+		let mut requests: Vec<EnergyRequest> = current_requests.into_iter().flatten().collect();
+		let mut offers: Vec<EnergyOffer> = current_offers.into_iter().flatten().collect();
+		let from_scratch = generate_trades(&mut requests, &mut offers, grid_request, grid_offer);
+		assert!(diff_trades(&from_scratch, &clearing.trades()).is_empty());
+		assert!(diff_trades(&from_scratch, &trades).is_empty());
+	}
+
+	#[test]
+	fn unaffordable_offer_only_goes_to_the_grid() {
+		let requests = vec![EnergyRequest {
+			amount: 10,
+			price: 2.0,
+			buyer: "buyer_1".into(),
+			..Default::default()
+		}];
+		let offers = vec![EnergyOffer {
+			amount: 10,
+			price: 1.9,
+			seller: "seller_1".into(),
+			..Default::default()
+		}];
+		let grid_request = EnergyRequest { price: 1.5, buyer: "grid".into(), ..Default::default() };
+		let grid_offer = EnergyOffer { price: 2.6, seller: "grid".into(), ..Default::default() };
+		let mode = MatchingMode::Price;
+		let mut clearing =
+			IncrementalClearing::new(requests, offers, &grid_request, &grid_offer, mode);
+
+		let (_, delta) = clearing.add_offer(EnergyOffer {
+			amount: 5,
+			price: 2.2,
+			seller: "seller_2".into(),
+			..Default::default()
+		});
+
+		assert!(delta.cancelled.is_empty());
+		assert_eq!(delta.created.len(), 1);
+		assert_eq!((delta.created[0].buyer.as_str(), delta.created[0].amount), ("grid", 5));
+		assert!(clearing.remove(OrderId::Request(1)).is_none());
+	}
+}
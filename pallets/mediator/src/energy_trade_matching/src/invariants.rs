@@ -0,0 +1,97 @@
+//! Invariants every cleared round has to satisfy, shared by the property tests and the fuzz
+//! target.
+//!
+//! The checks identify orders by participant, so every buyer and seller must place a single order
+//! and neither may share an id with the grid.
+
+use std::collections::BTreeMap;
+
+use crate::energy_trade_matching::{EnergyOffer, EnergyRequest, Trade};
+
+/// Check the trades formed for the given orders, describing the first violation found.
+///
+/// - every unit requested is bought and every unit offered is sold, from peers or the grid,
+///   except for requests whose buyers ran out of budget
+/// - no buyer spends more than their budget, allowing for rounding
+/// - no trade is for zero units
+/// - trades with the grid are at the grid's price
+/// - trades among peers are priced between the offer and the request, including the premium
+pub fn check(
+	requests: &[EnergyRequest],
+	offers: &[EnergyOffer],
+	grid_request: &EnergyRequest,
+	grid_offer: &EnergyOffer,
+	trades: &[Trade],
+) -> Result<(), String> {
+	let requests_by_buyer: BTreeMap<_, _> = requests.iter().map(|r| (&r.buyer, r)).collect();
+	let offers_by_seller: BTreeMap<_, _> = offers.iter().map(|o| (&o.seller, o)).collect();
+	let mut bought = BTreeMap::<&String, u64>::new();
+	let mut spent = BTreeMap::<&String, f64>::new();
+	let mut sold = BTreeMap::<&String, u64>::new();
+
+	for trade in trades {
+		if trade.amount == 0 {
+			return Err(format!("Trade of zero units: {:?}", trade))
+		}
+		*bought.entry(&trade.buyer).or_default() += trade.amount as u64;
+		*spent.entry(&trade.buyer).or_default() += trade.cost();
+		*sold.entry(&trade.seller).or_default() += trade.amount as u64;
+
+		let expected_price = if trade.seller == grid_offer.seller {
+			Some(grid_offer.price)
+		} else if trade.buyer == grid_request.buyer {
+			Some(grid_request.price)
+		} else {
+			None
+		};
+		match expected_price {
+			Some(price) if price.to_bits() != trade.price.to_bits() =>
+				return Err(format!("Trade with the grid not at the grid price: {:?}", trade)),
+			Some(_) => {},
+			None => {
+				let (Some(request), Some(offer)) =
+					(requests_by_buyer.get(&trade.buyer), offers_by_seller.get(&trade.seller))
+				else {
+					return Err(format!("Trade between unknown participants: {:?}", trade))
+				};
+				let limit = request.price + trade.certificate.premium;
+				if !(offer.price <= trade.price && trade.price <= limit) {
+					return Err(format!(
+						"Trade at {} outside of the offer price {} and the request limit {}",
+						trade.price, offer.price, limit
+					))
+				}
+			},
+		}
+	}
+
+	for request in requests {
+		let amount = bought.get(&request.buyer).copied().unwrap_or_default();
+		let served = match request.budget {
+			Some(_) => amount <= request.amount as u64,
+			None => amount == request.amount as u64,
+		};
+		if !served {
+			return Err(format!(
+				"{} requested {} but bought {}",
+				request.buyer, request.amount, amount
+			))
+		}
+		let cost = spent.get(&request.buyer).copied().unwrap_or_default();
+		if let Some(budget) = request.budget {
+			// Energy at a price of zero or less may be bought with no budget left
+			let budget = budget.max(0.0);
+			if cost - budget > 1e-9 * budget.max(1.0) {
+				return Err(format!("{} spent {} over a budget of {}", request.buyer, cost, budget))
+			}
+		}
+	}
+	for offer in offers {
+		let amount = sold.get(&offer.seller).copied().unwrap_or_default();
+		if amount != offer.amount as u64 {
+			return Err(format!("{} offered {} but sold {}", offer.seller, offer.amount, amount))
+		}
+	}
+
+	Ok(())
+}
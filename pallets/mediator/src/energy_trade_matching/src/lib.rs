@@ -1,144 +1,458 @@
+#[cfg(feature = "codec")]
+pub mod codec;
+pub mod hierarchical;
+pub mod imbalance;
+pub mod incremental;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod invariants;
+pub mod replay;
+pub mod report;
+pub mod scenario;
+pub mod settlement;
+pub mod simulation;
+pub mod trade_id;
+#[cfg(feature = "parallel")]
+pub mod parallel;
+
 pub mod energy_trade_matching {
     use std::cmp::Ordering;
+    use std::collections::VecDeque;
     use std::hash::{Hash, Hasher};
     use std::ops::{Add, Div};
     use serde::{Serialize, Deserialize};
 
-    pub fn generate_trades(energy_requests: &mut Vec<EnergyRequest>,
-                           energy_offers: &mut Vec<EnergyOffer>,
+    pub fn generate_trades(energy_requests: &mut [EnergyRequest],
+                           energy_offers: &mut [EnergyOffer],
                            grid_request: &EnergyRequest,
                            grid_offer: &EnergyOffer)
                            -> Vec<Trade> {
+        generate_trades_in_mode(energy_requests, energy_offers, grid_request, grid_offer, MatchingMode::default())
+    }
+
+    /// Like `generate_trades`, but breaking ties between equally priced offers as the mode says.
+    pub fn generate_trades_in_mode(energy_requests: &mut [EnergyRequest],
+                                   energy_offers: &mut [EnergyOffer],
+                                   grid_request: &EnergyRequest,
+                                   grid_offer: &EnergyOffer,
+                                   mode: MatchingMode)
+                                   -> Vec<Trade> {
+        let clearing = clear_peer_to_peer_in_mode(energy_requests, energy_offers, mode);
+        let mut trades = clearing.trades;
+
+        trades.extend(clear_with_grid(&clearing.unmatched_requests,
+                                      &clearing.unmatched_offers,
+                                      grid_request,
+                                      grid_offer));
+
+        trades
+    }
+
+    /// Match requests and offers among peers only, leaving whatever could not be matched for a
+    /// later stage, e.g. another partition or the grid.
+    ///
+    /// Requests are served in ascending order of price. Each takes the offer that is cheapest once
+    /// the premium for a preferred source is deducted, skipping offers of excluded sources, until
+    /// its buyer can not afford another unit of it.
+    pub fn clear_peer_to_peer(energy_requests: &mut [EnergyRequest],
+                              energy_offers: &mut [EnergyOffer])
+                              -> PeerToPeerClearing {
+        clear_peer_to_peer_in_mode(energy_requests, energy_offers, MatchingMode::default())
+    }
+
+    /// Like `clear_peer_to_peer`, but breaking ties between equally priced offers as the mode says.
+    pub fn clear_peer_to_peer_in_mode(energy_requests: &mut [EnergyRequest],
+                                      energy_offers: &mut [EnergyOffer],
+                                      mode: MatchingMode)
+                                      -> PeerToPeerClearing {
         let mut trades = Vec::<Trade>::new();
+        let mut unmatched_requests = Vec::<EnergyRequest>::new();
+
+        // Sort requests and offers, and queue the offers by source
+        energy_requests.sort_by(|e1, e2| e1.price.total_cmp(&e2.price));
+        energy_offers.sort_by(|e1, e2| mode.break_tie(e1.price.total_cmp(&e2.price), e1, e2));
+        let mut offer_queues = OfferQueues::new(energy_offers.iter().map(|o| ((), o, o.amount)), mode);
+
+        // Match trades, keeping the requested amount that could not be matched
+        for r in energy_requests.iter() {
+            let mut spent = 0.0;
+            let remaining = offer_queues.match_request(r, |_, trade| {
+                spent += trade.cost();
+                trades.push(trade);
+            });
+            if remaining > 0 {
+                unmatched_requests.push(r.remainder(remaining, spent));
+            }
+        }
+
+        PeerToPeerClearing { trades, unmatched_requests, unmatched_offers: offer_queues.into_remaining() }
+    }
+
+    /// Offers queued by source in the order they are given, each with a key of the caller's, its
+    /// position in that order and the amount left of it
+    pub(crate) struct OfferQueues<'a, K> {
+        queues: [VecDeque<(K, usize, &'a EnergyOffer, u16)>; EnergySource::ALL.len()],
+        mode: MatchingMode,
+    }
 
-        // Sort requests and offers amd turn to iters
-        energy_requests.sort_by(|e1, e2| e1.price.partial_cmp(&e2.price).unwrap());
-        energy_offers.sort_by(|e1, e2| e1.price.partial_cmp(&e2.price).unwrap());
-        let mut request_iter = energy_requests.iter();
-        let mut offer_iter = energy_offers.iter();
-
-        // Get first request and offer
-        let mut req = request_iter.next();
-        let mut off = offer_iter.next();
-
-        // Create variables to store temporary surplus requests and offers
-        let mut surplus_req: EnergyRequest;
-        let mut surplus_off: EnergyOffer;
-
-        // Match trades
-        while req.is_some() && off.is_some() {
-            match (req, off) {
-                (Some(r), Some(o)) => {
-                    let t;
-
-                    if r.price >= o.price {
-                        let average_price = r.price.add(o.price).div(2.0);
-
-                        // Create match for request and offer, and handle potential surplus
-                        match r.amount.partial_cmp(&o.amount).expect("Both values are numbers") {
-                            Ordering::Less => {
-                                // The offered amount exceeds whats requested
-                                t = Trade { amount: r.amount, price: average_price, buyer: r.buyer.clone(), seller: o.seller.clone() };
-                                req = request_iter.next();
-
-                                surplus_off = EnergyOffer { amount: o.amount.clone() - r.amount.clone(), price: o.price.clone(), seller: o.seller.clone() };
-                                off = Some(&surplus_off);
-                            }
-                            Ordering::Equal => {
-                                // The same amount is requested and offered
-                                t = Trade { amount: o.amount, price: average_price, buyer: r.buyer.clone(), seller: o.seller.clone() };
-                                req = request_iter.next();
-                                off = offer_iter.next();
-                            }
-                            Ordering::Greater => {
-                                // The requested amount exceeds whats offered
-                                t = Trade { amount: o.amount, price: average_price, buyer: r.buyer.clone(), seller: o.seller.clone() };
-                                off = offer_iter.next();
-
-                                surplus_req = EnergyRequest { amount: r.amount.clone() - o.amount.clone(), price: r.price.clone(), buyer: r.buyer.clone() };
-                                req = Some(&surplus_req);
-                            }
-                        }
-                    } else {
-                        t = Trade { amount: r.amount, price: grid_offer.price, buyer: r.buyer.clone(), seller: grid_offer.seller.clone() };
-                        req = request_iter.next();
-                    }
-                    trades.push(t);
+    impl<'a, K: Copy> OfferQueues<'a, K> {
+        /// Queue the offers, sorted the way `clear_peer_to_peer_in_mode` sorts them.
+        pub(crate) fn new(sorted_offers: impl IntoIterator<Item = (K, &'a EnergyOffer, u16)>,
+                          mode: MatchingMode)
+                          -> Self {
+            let mut queues: [VecDeque<_>; EnergySource::ALL.len()] = Default::default();
+            let sorted_offers = sorted_offers.into_iter().enumerate();
+            for (index, (key, o, left)) in sorted_offers.filter(|&(_, (_, _, left))| left > 0) {
+                queues[o.source as usize].push_back((key, index, o, left));
+            }
+
+            OfferQueues { queues, mode }
+        }
+
+        /// The offer the request can afford that is cheapest to its buyer, with the amount left of
+        /// it and the premium the buyer adds for its source. Offers the mode can not tell apart
+        /// are taken in the order they are given, whatever their source.
+        fn best_offer_for(&self, r: &EnergyRequest) -> Option<(K, &'a EnergyOffer, u16, f32)> {
+            self.queues.iter()
+                .filter_map(|queue| queue.front())
+                .filter(|(_, _, o, _)| r.accepts(o))
+                .map(|&(key, index, o, left)| (key, index, o, left, r.origin.premium_for(o.source)))
+                .min_by(|(_, i1, o1, _, p1), (_, i2, o2, _, p2)| {
+                    self.mode.break_tie((o1.price - p1).total_cmp(&(o2.price - p2)), o1, o2)
+                        .then(i1.cmp(i2))
+                })
+                .map(|(key, _, o, left, premium)| (key, o, left, premium))
+        }
+
+        fn take(&mut self, source: EnergySource, amount: u16) {
+            let queue = &mut self.queues[source as usize];
+            if let Some((_, _, _, left)) = queue.front_mut() {
+                *left -= amount;
+                if *left == 0 {
+                    queue.pop_front();
                 }
-                _ => break
             }
         }
 
-        // Match remaining requests with grid
-        while req.is_some() {
-            let t = Trade { amount: req.unwrap().amount, price: grid_offer.price, buyer: req.unwrap().buyer.clone(), seller: grid_offer.seller.clone() };
-            req = request_iter.next();
-            trades.push(t);
+        /// Match the request with the best offers for it one after another, passing on each trade
+        /// along with the key of its offer, and return the amount left unmatched.
+        pub(crate) fn match_request(&mut self, r: &EnergyRequest, mut on_trade: impl FnMut(K, Trade)) -> u16 {
+            let mut remaining = r.amount;
+            let mut spent = 0.0;
+
+            while remaining > 0 {
+                // Stop when no peer is willing to sell this cheaply
+                let Some((key, o, offered, premium)) = self.best_offer_for(r) else { break };
+                // Averaged in double precision, so that it can not overflow
+                let average_price = (r.price.add(premium) as f64).add(o.price as f64).div(2.0) as f32;
+                let amount = remaining.min(offered).min(r.affordable(average_price, spent));
+                // Stop when the buyer can not afford another unit
+                if amount == 0 {
+                    break;
+                }
+
+                on_trade(key, Trade {
+                    amount,
+                    price: average_price,
+                    buyer: r.buyer.clone(),
+                    seller: o.seller.clone(),
+                    certificate: Certificate { source: o.source, premium, carbon_intensity: o.carbon_intensity },
+                });
+                self.take(o.source, amount);
+                remaining -= amount;
+                spent += amount as f64 * average_price as f64;
+            }
+
+            remaining
         }
 
-        // Match remaining offers with grid
-        while off.is_some() {
-            let t = Trade { amount: off.unwrap().amount, price: grid_request.price, buyer: grid_request.buyer.clone(), seller: off.unwrap().seller.clone() };
-            off = offer_iter.next();
-            trades.push(t);
+        /// The offers not fully matched, with their remaining amounts, in the order they are given,
+        /// which is ascending by price
+        fn into_remaining(self) -> Vec<EnergyOffer> {
+            let mut offers: Vec<(usize, &EnergyOffer, u16)> = self.queues.into_iter()
+                .flatten()
+                .map(|(_, index, o, left)| (index, o, left))
+                .collect();
+            offers.sort_by_key(|&(index, _, _)| index);
+
+            offers.into_iter().map(|(_, o, left)| EnergyOffer { amount: left, ..o.clone() }).collect()
         }
+    }
 
-        trades
+    /// Match all given requests and offers with the grid. Requests are bought from the grid only
+    /// as far as their budgets allow, the rest is left unserved.
+    pub fn clear_with_grid(energy_requests: &[EnergyRequest],
+                           energy_offers: &[EnergyOffer],
+                           grid_request: &EnergyRequest,
+                           grid_offer: &EnergyOffer)
+                           -> Vec<Trade> {
+        let requests_to_grid = energy_requests.iter()
+            .map(|r| (r, r.amount.min(r.affordable(grid_offer.price, 0.0))))
+            .filter(|&(_, amount)| amount > 0)
+            .map(|(r, amount)| Trade {
+                amount,
+                price: grid_offer.price,
+                buyer: r.buyer.clone(),
+                seller: grid_offer.seller.clone(),
+                certificate: Certificate {
+                    source: grid_offer.source,
+                    premium: 0.0,
+                    carbon_intensity: grid_offer.carbon_intensity,
+                },
+            });
+        let offers_to_grid = energy_offers.iter()
+            .map(|o| Trade {
+                amount: o.amount,
+                price: grid_request.price,
+                buyer: grid_request.buyer.clone(),
+                seller: o.seller.clone(),
+                certificate: Certificate {
+                    source: o.source,
+                    premium: 0.0,
+                    carbon_intensity: o.carbon_intensity,
+                },
+            });
+
+        requests_to_grid.chain(offers_to_grid).collect()
+    }
+
+    /// Pool the requests and offers left over by the peer-to-peer clearings of several
+    /// partitions, match them across partitions, and match what is still left with the grid.
+    pub fn clear_residuals(clearings: &[PeerToPeerClearing],
+                           grid_request: &EnergyRequest,
+                           grid_offer: &EnergyOffer,
+                           mode: MatchingMode)
+                           -> ResidualClearing {
+        let mut residual_requests: Vec<EnergyRequest> =
+            clearings.iter().flat_map(|c| c.unmatched_requests.iter().cloned()).collect();
+        let mut residual_offers: Vec<EnergyOffer> =
+            clearings.iter().flat_map(|c| c.unmatched_offers.iter().cloned()).collect();
+
+        let clearing = clear_peer_to_peer_in_mode(&mut residual_requests, &mut residual_offers, mode);
+        let grid_trades = clear_with_grid(&clearing.unmatched_requests,
+                                          &clearing.unmatched_offers,
+                                          grid_request,
+                                          grid_offer);
+
+        ResidualClearing { trades: clearing.trades, grid_trades }
+    }
+
+    /// Trades formed among peers, along with the requests and offers left for the grid
+    #[derive(Serialize, Deserialize, Debug, Clone, Default)]
+    pub struct PeerToPeerClearing {
+        pub trades: Vec<Trade>,
+        pub unmatched_requests: Vec<EnergyRequest>,
+        pub unmatched_offers: Vec<EnergyOffer>,
+    }
+
+    /// Trades formed across partitions from what they left over, and the trades of what was still
+    /// left with the grid
+    #[derive(Serialize, Deserialize, Debug, Clone, Default)]
+    pub struct ResidualClearing {
+        pub trades: Vec<Trade>,
+        pub grid_trades: Vec<Trade>,
+    }
+
+    /// Orders of a single microgrid or market zone, which can be cleared independently of others
+    #[derive(Serialize, Deserialize, Debug, Clone, Default)]
+    pub struct MarketPartition {
+        pub zone: String,
+        pub requests: Vec<EnergyRequest>,
+        pub offers: Vec<EnergyOffer>,
+    }
+
+    /// How equally priced offers are told apart
+    #[cfg_attr(feature = "codec", derive(parity_scale_codec::Encode, parity_scale_codec::Decode,
+                                         scale_info::TypeInfo, parity_scale_codec::MaxEncodedLen))]
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum MatchingMode {
+        /// Only by price, keeping the order offers are given in
+        #[default]
+        Price,
+        /// Preferring offers with a lower carbon intensity
+        LowCarbon,
+    }
+
+    impl MatchingMode {
+        /// Order two offers, given their order by price
+        pub(crate) fn break_tie(self, by_price: Ordering, o1: &EnergyOffer, o2: &EnergyOffer) -> Ordering {
+            match self {
+                MatchingMode::Price => by_price,
+                MatchingMode::LowCarbon =>
+                    by_price.then(o1.carbon_intensity.total_cmp(&o2.carbon_intensity)),
+            }
+        }
+    }
+
+    /// How the energy of an offer is generated
+    #[cfg_attr(feature = "codec", derive(parity_scale_codec::Encode, parity_scale_codec::Decode,
+                                         scale_info::TypeInfo, parity_scale_codec::MaxEncodedLen))]
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+    pub enum EnergySource {
+        Pv,
+        Wind,
+        Chp,
+        /// Unknown or mixed sources, as supplied by the grid
+        #[default]
+        GridMix,
+    }
+
+    impl EnergySource {
+        pub const ALL: [EnergySource; 4] =
+            [EnergySource::Pv, EnergySource::Wind, EnergySource::Chp, EnergySource::GridMix];
+    }
+
+    /// Sources a buyer prefers or refuses, by default none
+    #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+    pub struct SourcePreference {
+        #[serde(default)]
+        pub preferred: Vec<EnergySource>,
+        /// Added per unit to the buyer's price when buying from a preferred source
+        #[serde(default)]
+        pub premium: f32,
+        /// Sources the buyer never buys from among peers
+        #[serde(default)]
+        pub excluded: Vec<EnergySource>,
+    }
+
+    impl SourcePreference {
+        pub fn is_empty(&self) -> bool {
+            self.preferred.is_empty() && self.excluded.is_empty()
+        }
+
+        /// Premium the buyer pays on top of their price for energy of the given source
+        pub fn premium_for(&self, source: EnergySource) -> f32 {
+            if self.preferred.contains(&source) { self.premium } else { 0.0 }
+        }
+    }
+
+    /// Certificate of origin of the energy of a trade
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+    pub struct Certificate {
+        pub source: EnergySource,
+        /// Premium per unit the buyer paid for the source, included in the trade's price
+        pub premium: f32,
+        /// Grams of CO2 emitted per unit
+        #[serde(default)]
+        pub carbon_intensity: f32,
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
+    #[derive(Serialize, Deserialize, Debug, Default)]
     pub struct EnergyRequest {
         pub amount: u16,
         pub price: f32,
         pub buyer: String,
+        #[serde(default, skip_serializing_if = "SourcePreference::is_empty")]
+        pub origin: SourcePreference,
+        /// Most the buyer may spend on the request, from peers and the grid together
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub budget: Option<f64>,
+    }
+
+    impl EnergyRequest {
+        /// Whether the buyer is willing to buy from the offer among peers, including the premium
+        /// for its source. Prices that are not finite are never matched among peers.
+        pub fn accepts(&self, offer: &EnergyOffer) -> bool {
+            let limit = self.price.add(self.origin.premium_for(offer.source));
+            !self.origin.excluded.contains(&offer.source)
+                && limit.is_finite()
+                && offer.price.is_finite()
+                && limit >= offer.price
+        }
+
+        /// Units the buyer can afford at the given price, having spent the given sum on the request
+        /// already. Without a budget, or at a price of zero or less, there is no limit.
+        pub fn affordable(&self, price: f32, spent: f64) -> u16 {
+            let Some(budget) = self.budget else { return u16::MAX };
+            if price <= 0.0 {
+                return u16::MAX;
+            }
+
+            let left = budget - spent;
+            let mut units = (left / price as f64).floor().clamp(0.0, u16::MAX as f64) as u16;
+            // Division may round up to the next unit
+            if units > 0 && units as f64 * price as f64 > left {
+                units -= 1;
+            }
+            units
+        }
+
+        /// The part of the request left for a later stage, with the given amount and what is left
+        /// of the budget after spending the given sum.
+        pub fn remainder(&self, amount: u16, spent: f64) -> EnergyRequest {
+            EnergyRequest { amount, budget: self.budget.map(|budget| budget - spent), ..self.clone() }
+        }
     }
 
     impl Clone for EnergyRequest {
         fn clone(&self) -> Self {
             EnergyRequest {
-                amount: self.amount.clone(),
-                price: self.price.clone(),
+                amount: self.amount,
+                price: self.price,
                 buyer: self.buyer.clone(),
+                origin: self.origin.clone(),
+                budget: self.budget,
             }
         }
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
+    #[derive(Serialize, Deserialize, Debug, Default)]
     pub struct EnergyOffer {
         pub amount: u16,
         pub price: f32,
         pub seller: String,
+        #[serde(default)]
+        pub source: EnergySource,
+        /// Grams of CO2 emitted per unit
+        #[serde(default)]
+        pub carbon_intensity: f32,
     }
 
     impl Clone for EnergyOffer {
         fn clone(&self) -> Self {
             EnergyOffer {
-                amount: self.amount.clone(),
-                price: self.price.clone(),
+                amount: self.amount,
+                price: self.price,
                 seller: self.seller.clone(),
+                source: self.source,
+                carbon_intensity: self.carbon_intensity,
             }
         }
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
+    #[derive(Serialize, Deserialize, Debug, Default)]
     pub struct Trade {
         pub amount: u16,
         pub price: f32,
         pub buyer: String,
         pub seller: String,
+        #[serde(default)]
+        pub certificate: Certificate,
     }
 
     impl Clone for Trade {
         fn clone(&self) -> Self {
             Trade {
-                amount: self.amount.clone(),
-                price: self.price.clone(),
+                amount: self.amount,
+                price: self.price,
                 buyer: self.buyer.clone(),
                 seller: self.seller.clone(),
+                certificate: self.certificate,
             }
         }
     }
 
+    impl Trade {
+        /// Sum the buyer pays for the traded amount
+        pub fn cost(&self) -> f64 {
+            self.amount as f64 * self.price as f64
+        }
+
+        /// Grams of CO2 emitted for the traded amount
+        pub fn emissions(&self) -> f64 {
+            self.amount as f64 * self.certificate.carbon_intensity as f64
+        }
+    }
+
     impl Hash for Trade {
         fn hash<H: Hasher>(&self, state: &mut H) {
             self.amount.hash(state);
@@ -156,8 +470,8 @@ pub mod energy_trade_matching {
         fn empty_list_returns_no_trades() {
             let mut requests = Vec::<EnergyRequest>::new();
             let mut offers = Vec::<EnergyOffer>::new();
-            let grid_request = EnergyRequest { amount: 10, price: 2.2, buyer: "grid".into() };
-            let grid_offer = EnergyOffer { amount: 10, price: 2.2, seller: "grid".into() };
+            let grid_request = EnergyRequest { amount: 10, price: 2.2, buyer: "grid".into(), ..Default::default() };
+            let grid_offer = EnergyOffer { amount: 10, price: 2.2, seller: "grid".into(), ..Default::default() };
 
             let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
 
@@ -167,13 +481,13 @@ pub mod energy_trade_matching {
         #[test]
         fn empty_offers_returns_request_trades() {
             let mut requests = Vec::<EnergyRequest>::from([
-                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into() },
-                EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_2".into() },
-                EnergyRequest { amount: 10, price: 2.3, buyer: "buyer_3".into() }
+                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_2".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.3, buyer: "buyer_3".into(), ..Default::default() }
             ]);
             let mut offers = Vec::<EnergyOffer>::new();
-            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into() };
-            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into() };
+            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into(), ..Default::default() };
+            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into(), ..Default::default() };
 
             let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
 
@@ -184,12 +498,12 @@ pub mod energy_trade_matching {
         fn empty_requests_returns_offer_trades() {
             let mut requests = Vec::<EnergyRequest>::new();
             let mut offers = Vec::<EnergyOffer>::from([
-                EnergyOffer { amount: 10, price: 1.0, seller: "seller_1".into() },
-                EnergyOffer { amount: 10, price: 2.0, seller: "seller_2".into() },
-                EnergyOffer { amount: 10, price: 2.3, seller: "seller_3".into() }
+                EnergyOffer { amount: 10, price: 1.0, seller: "seller_1".into(), ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.0, seller: "seller_2".into(), ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.3, seller: "seller_3".into(), ..Default::default() }
             ]);
-            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into() };
-            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into() };
+            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into(), ..Default::default() };
+            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into(), ..Default::default() };
 
             let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
 
@@ -199,17 +513,17 @@ pub mod energy_trade_matching {
         #[test]
         fn requests_and_offers_are_matched_to_trades() {
             let mut requests = Vec::<EnergyRequest>::from([
-                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into() },
-                EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_2".into() },
-                EnergyRequest { amount: 10, price: 2.3, buyer: "buyer_3".into() }
+                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_2".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.3, buyer: "buyer_3".into(), ..Default::default() }
             ]);
             let mut offers = Vec::<EnergyOffer>::from([
-                EnergyOffer { amount: 10, price: 1.9, seller: "seller_1".into() },
-                EnergyOffer { amount: 10, price: 2.0, seller: "seller_2".into() },
-                EnergyOffer { amount: 10, price: 2.3, seller: "seller_3".into() }
+                EnergyOffer { amount: 10, price: 1.9, seller: "seller_1".into(), ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.0, seller: "seller_2".into(), ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.3, seller: "seller_3".into(), ..Default::default() }
             ]);
-            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into() };
-            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into() };
+            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into(), ..Default::default() };
+            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into(), ..Default::default() };
 
             let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
 
@@ -220,22 +534,22 @@ pub mod energy_trade_matching {
         #[test]
         fn cheap_request_is_sold_to_the_grid_and_everything_else_is_peer_to_peer() {
             let mut requests = Vec::<EnergyRequest>::from([
-                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into() },
-                EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_2".into() },
-                EnergyRequest { amount: 10, price: 2.1, buyer: "buyer_3".into() },
-                EnergyRequest { amount: 10, price: 2.4, buyer: "buyer_4".into() },
-                EnergyRequest { amount: 10, price: 2.8, buyer: "buyer_5".into() }
+                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_2".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.1, buyer: "buyer_3".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.4, buyer: "buyer_4".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.8, buyer: "buyer_5".into(), ..Default::default() }
             ]);
             let mut offers = Vec::<EnergyOffer>::from([
-                EnergyOffer { amount: 10, price: 2.0, seller: "seller_1".into() },
-                EnergyOffer { amount: 10, price: 2.1, seller: "seller_2".into() },
-                EnergyOffer { amount: 10, price: 2.3, seller: "seller_3".into() },
-                EnergyOffer { amount: 5, price: 2.3, seller: "seller_4".into() },
-                EnergyOffer { amount: 5, price: 2.7, seller: "seller_5".into() }
+                EnergyOffer { amount: 10, price: 2.0, seller: "seller_1".into(), ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.1, seller: "seller_2".into(), ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.3, seller: "seller_3".into(), ..Default::default() },
+                EnergyOffer { amount: 5, price: 2.3, seller: "seller_4".into(), ..Default::default() },
+                EnergyOffer { amount: 5, price: 2.7, seller: "seller_5".into(), ..Default::default() }
             ]);
 
-            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into() };
-            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into() };
+            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into(), ..Default::default() };
+            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into(), ..Default::default() };
 
             let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
 
@@ -248,49 +562,189 @@ pub mod energy_trade_matching {
         #[test]
         fn offered_and_traded_amounts_match_including_grid() {
             let mut requests = Vec::<EnergyRequest>::from([
-                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into() },
-                EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_2".into() },
-                EnergyRequest { amount: 10, price: 2.1, buyer: "buyer_3".into() },
-                EnergyRequest { amount: 10, price: 2.4, buyer: "buyer_4".into() },
-                EnergyRequest { amount: 10, price: 2.8, buyer: "buyer_5".into() }
+                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_2".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.1, buyer: "buyer_3".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.4, buyer: "buyer_4".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.8, buyer: "buyer_5".into(), ..Default::default() }
             ]);
             let mut offers = Vec::<EnergyOffer>::from([
-                EnergyOffer { amount: 10, price: 2.0, seller: "seller_1".into() },
-                EnergyOffer { amount: 10, price: 2.1, seller: "seller_2".into() },
-                EnergyOffer { amount: 10, price: 2.3, seller: "seller_3".into() },
-                EnergyOffer { amount: 5, price: 2.3, seller: "seller_4".into() },
-                EnergyOffer { amount: 5, price: 2.7, seller: "seller_5".into() }
+                EnergyOffer { amount: 10, price: 2.0, seller: "seller_1".into(), ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.1, seller: "seller_2".into(), ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.3, seller: "seller_3".into(), ..Default::default() },
+                EnergyOffer { amount: 5, price: 2.3, seller: "seller_4".into(), ..Default::default() },
+                EnergyOffer { amount: 5, price: 2.7, seller: "seller_5".into(), ..Default::default() }
             ]);
 
-            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into() };
-            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into() };
+            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into(), ..Default::default() };
+            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into(), ..Default::default() };
 
             let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
 
             trades.iter().for_each(|t| println!("{}", t.amount));
 
-            assert_eq!(offers.iter().map(|o| o.amount).sum::<u16>() + 10, trades.iter().map(|t| t.amount).sum::<u16>());
+            assert_eq!(offers.iter().map(|o| o.amount as u64).sum::<u64>() + 10, trades.iter().map(|t| t.amount as u64).sum::<u64>());
+        }
+
+        /// Two full requests served by two full offers, trading more than a `u16` holds
+        fn trades_beyond_u16() -> (Vec<EnergyOffer>, Vec<Trade>) {
+            let mut requests = Vec::<EnergyRequest>::from([
+                EnergyRequest { amount: u16::MAX, price: 2.4, buyer: "buyer_1".into(), ..Default::default() },
+                EnergyRequest { amount: u16::MAX, price: 2.8, buyer: "buyer_2".into(), ..Default::default() }
+            ]);
+            let mut offers = Vec::<EnergyOffer>::from([
+                EnergyOffer { amount: u16::MAX, price: 2.0, seller: "seller_1".into(), ..Default::default() },
+                EnergyOffer { amount: u16::MAX, price: 2.1, seller: "seller_2".into(), ..Default::default() }
+            ]);
+
+            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into(), ..Default::default() };
+            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into(), ..Default::default() };
+
+            let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
+            (offers, trades)
+        }
+
+        /// The assertion of `offered_and_traded_amounts_match_including_grid` as it was, summing
+        /// as `u16`
+        #[test]
+        #[cfg(debug_assertions)]
+        #[should_panic(expected = "attempt to add with overflow")]
+        fn summing_traded_amounts_as_u16_overflows() {
+            let (offers, trades) = trades_beyond_u16();
+
+            assert_eq!(offers.iter().map(|o| o.amount).sum::<u16>(), trades.iter().map(|t| t.amount).sum::<u16>());
+        }
+
+        #[test]
+        fn traded_amounts_beyond_u16_are_summed_as_u64() {
+            let (offers, trades) = trades_beyond_u16();
+
+            assert_eq!(trades.iter().map(|t| t.amount).try_fold(0u16, u16::checked_add), None);
+            assert_eq!(offers.iter().map(|o| o.amount as u64).sum::<u64>(), trades.iter().map(|t| t.amount as u64).sum::<u64>());
+        }
+
+        #[test]
+        fn peer_to_peer_clearing_leaves_unmatched_orders_for_the_grid() {
+            let mut requests = Vec::<EnergyRequest>::from([
+                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.4, buyer: "buyer_2".into(), ..Default::default() }
+            ]);
+            let mut offers = Vec::<EnergyOffer>::from([
+                EnergyOffer { amount: 15, price: 2.0, seller: "seller_1".into(), ..Default::default() }
+            ]);
+
+            let clearing = clear_peer_to_peer(&mut requests, &mut offers);
+
+            assert_eq!(clearing.trades.len(), 1);
+            assert_eq!(clearing.unmatched_requests.len(), 1);
+            assert_eq!(clearing.unmatched_requests[0].buyer, "buyer_1");
+            assert_eq!(clearing.unmatched_offers.len(), 1);
+            assert_eq!(clearing.unmatched_offers[0].amount, 5);
+        }
+
+        #[test]
+        fn preferred_source_is_bought_at_a_premium() {
+            let origin = SourcePreference { preferred: vec![EnergySource::Pv], premium: 0.5, ..Default::default() };
+            let mut requests = vec![EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_1".into(), origin, ..Default::default() }];
+            let mut offers = vec![
+                EnergyOffer { amount: 10, price: 1.8, seller: "seller_1".into(), source: EnergySource::GridMix, ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.2, seller: "seller_2".into(), source: EnergySource::Pv, ..Default::default() },
+            ];
+
+            let clearing = clear_peer_to_peer(&mut requests, &mut offers);
+
+            // The cheaper grid mix is worth less to the buyer than PV with its premium deducted
+            assert_eq!(clearing.trades.len(), 1);
+            assert_eq!(clearing.trades[0].seller, "seller_2");
+            assert_eq!(clearing.trades[0].price, 2.35);
+            assert_eq!(clearing.trades[0].certificate, Certificate { source: EnergySource::Pv, premium: 0.5, ..Default::default() });
+            assert_eq!(clearing.unmatched_offers[0].seller, "seller_1");
+        }
+
+        #[test]
+        fn excluded_source_is_not_bought_from_peers() {
+            let origin = SourcePreference { excluded: vec![EnergySource::Chp], ..Default::default() };
+            let mut requests = vec![EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_1".into(), origin, ..Default::default() }];
+            let mut offers = vec![EnergyOffer { amount: 10, price: 1.5, seller: "seller_1".into(), source: EnergySource::Chp, ..Default::default() }];
+            let grid_request = EnergyRequest { amount: 10, price: 1.2, buyer: "grid".into(), ..Default::default() };
+            let grid_offer = EnergyOffer { amount: 10, price: 2.6, seller: "grid".into(), ..Default::default() };
+
+            let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
+
+            assert_eq!(trades.len(), 2);
+            assert_eq!((trades[0].seller.as_str(), trades[0].certificate.source), ("grid", EnergySource::GridMix));
+            assert_eq!((trades[1].buyer.as_str(), trades[1].certificate.source), ("grid", EnergySource::Chp));
+        }
+
+        #[test]
+        fn buyer_stops_buying_from_peers_at_their_budget() {
+            let mut requests = vec![EnergyRequest { amount: 10, price: 2.4, buyer: "buyer_1".into(), budget: Some(5.0), ..Default::default() }];
+            let mut offers = vec![EnergyOffer { amount: 10, price: 2.0, seller: "seller_1".into(), ..Default::default() }];
+
+            let clearing = clear_peer_to_peer(&mut requests, &mut offers);
+
+            // At 2.2 per unit, 2 units are all the budget allows
+            assert_eq!(clearing.trades.len(), 1);
+            assert_eq!(clearing.trades[0].amount, 2);
+            assert_eq!(clearing.unmatched_requests[0].amount, 8);
+            assert!((clearing.unmatched_requests[0].budget.unwrap() - 0.6).abs() < 1e-6);
+            assert_eq!(clearing.unmatched_offers[0].amount, 8);
+        }
+
+        #[test]
+        fn equally_priced_offers_of_different_sources_are_matched_in_input_order() {
+            let mut requests = vec![EnergyRequest { amount: 10, price: 2.2, buyer: "buyer_1".into(), ..Default::default() }];
+            let mut offers = vec![
+                EnergyOffer { amount: 10, price: 2.0, seller: "wind".into(), source: EnergySource::Wind, ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.0, seller: "pv".into(), source: EnergySource::Pv, ..Default::default() },
+            ];
+
+            let clearing = clear_peer_to_peer(&mut requests, &mut offers);
+
+            assert_eq!(clearing.trades.len(), 1);
+            assert_eq!(clearing.trades[0].seller, "wind");
+            assert_eq!(clearing.unmatched_offers.len(), 1);
+            assert_eq!(clearing.unmatched_offers[0].seller, "pv");
+        }
+
+        #[test]
+        fn low_carbon_mode_prefers_cleaner_offers_at_equal_price() {
+            let offers = vec![
+                EnergyOffer { amount: 10, price: 2.0, seller: "chp".into(), carbon_intensity: 400.0, ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.0, seller: "wind".into(), carbon_intensity: 10.0, ..Default::default() },
+            ];
+            let request = EnergyRequest { amount: 10, price: 2.2, buyer: "buyer_1".into(), ..Default::default() };
+
+            let by_price = clear_peer_to_peer(&mut [request.clone()], &mut offers.clone());
+            let low_carbon = clear_peer_to_peer_in_mode(&mut [request], &mut offers.clone(), MatchingMode::LowCarbon);
+
+            assert_eq!(by_price.trades[0].seller, "chp");
+            assert_eq!(by_price.trades[0].emissions(), 4000.0);
+            assert_eq!(low_carbon.trades[0].seller, "wind");
+            assert_eq!(low_carbon.trades[0].emissions(), 100.0);
+            assert_eq!(low_carbon.trades[0].price, by_price.trades[0].price);
         }
 
         #[test]
+        #[allow(clippy::manual_contains)]
         fn all_buyers_and_sellers_are_included() {
             let mut requests = Vec::<EnergyRequest>::from([
-                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into() },
-                EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_2".into() },
-                EnergyRequest { amount: 10, price: 2.1, buyer: "buyer_3".into() },
-                EnergyRequest { amount: 10, price: 2.4, buyer: "buyer_4".into() },
-                EnergyRequest { amount: 10, price: 2.8, buyer: "buyer_5".into() }
+                EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.0, buyer: "buyer_2".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.1, buyer: "buyer_3".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.4, buyer: "buyer_4".into(), ..Default::default() },
+                EnergyRequest { amount: 10, price: 2.8, buyer: "buyer_5".into(), ..Default::default() }
             ]);
             let mut offers = Vec::<EnergyOffer>::from([
-                EnergyOffer { amount: 10, price: 2.0, seller: "seller_1".into() },
-                EnergyOffer { amount: 10, price: 2.1, seller: "seller_2".into() },
-                EnergyOffer { amount: 10, price: 2.3, seller: "seller_3".into() },
-                EnergyOffer { amount: 5, price: 2.3, seller: "seller_4".into() },
-                EnergyOffer { amount: 5, price: 2.7, seller: "seller_5".into() }
+                EnergyOffer { amount: 10, price: 2.0, seller: "seller_1".into(), ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.1, seller: "seller_2".into(), ..Default::default() },
+                EnergyOffer { amount: 10, price: 2.3, seller: "seller_3".into(), ..Default::default() },
+                EnergyOffer { amount: 5, price: 2.3, seller: "seller_4".into(), ..Default::default() },
+                EnergyOffer { amount: 5, price: 2.7, seller: "seller_5".into(), ..Default::default() }
             ]);
 
-            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into() };
-            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into() };
+            let grid_request = EnergyRequest { amount: 10, price: 2.0, buyer: "grid".into(), ..Default::default() };
+            let grid_offer = EnergyOffer { amount: 10, price: 2.0, seller: "grid".into(), ..Default::default() };
 
             let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
 
@@ -0,0 +1,206 @@
+//! Parallel clearing of independent market partitions.
+//!
+//! Each partition, e.g. a microgrid or market zone, is first cleared peer-to-peer on its own.
+//! Whatever is left in the partitions is then pooled and cleared across partitions, before the
+//! final remainder is matched with the grid.
+
+use rayon::{prelude::*, ThreadPool};
+
+use crate::energy_trade_matching::{
+	clear_peer_to_peer_in_mode, clear_residuals, EnergyOffer, EnergyRequest, MarketPartition,
+	MatchingMode, PeerToPeerClearing, Trade,
+};
+
+/// Clear all partitions concurrently on the global thread pool, and pool the residuals, breaking
+/// ties between equally priced offers at every stage as the mode says.
+pub fn generate_trades_parallel(
+	partitions: &mut [MarketPartition],
+	grid_request: &EnergyRequest,
+	grid_offer: &EnergyOffer,
+	mode: MatchingMode,
+) -> Vec<Trade> {
+	let cleared: Vec<PeerToPeerClearing> = partitions
+		.par_iter_mut()
+		.map(|p| clear_peer_to_peer_in_mode(&mut p.requests, &mut p.offers, mode))
+		.collect();
+
+	let mut trades: Vec<Trade> = cleared.iter().flat_map(|c| c.trades.iter().cloned()).collect();
+
+	// Second stage, matching residuals across partitions and finally with the grid
+	let residual = clear_residuals(&cleared, grid_request, grid_offer, mode);
+	trades.extend(residual.trades);
+	trades.extend(residual.grid_trades);
+
+	trades
+}
+
+/// Same as [`generate_trades_parallel`], but runs on the given thread pool.
+pub fn generate_trades_parallel_in(
+	pool: &ThreadPool,
+	partitions: &mut [MarketPartition],
+	grid_request: &EnergyRequest,
+	grid_offer: &EnergyOffer,
+	mode: MatchingMode,
+) -> Vec<Trade> {
+	pool.install(|| generate_trades_parallel(partitions, grid_request, grid_offer, mode))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn grid() -> (EnergyRequest, EnergyOffer) {
+		(
+			EnergyRequest { amount: 10, price: 1.5, buyer: "grid".into(), ..Default::default() },
+			EnergyOffer { amount: 10, price: 3.0, seller: "grid".into(), ..Default::default() },
+		)
+	}
+
+	#[test]
+	fn residuals_are_matched_across_partitions_before_the_grid() {
+		let mut partitions = vec![
+			MarketPartition {
+				zone: "mg_1".into(),
+				requests: vec![EnergyRequest {
+					amount: 10,
+					price: 2.5,
+					buyer: "buyer_1".into(),
+					..Default::default()
+				}],
+				offers: vec![],
+			},
+			MarketPartition {
+				zone: "mg_2".into(),
+				requests: vec![],
+				offers: vec![EnergyOffer {
+					amount: 10,
+					price: 2.0,
+					seller: "seller_1".into(),
+					..Default::default()
+				}],
+			},
+		];
+		let (grid_request, grid_offer) = grid();
+
+		let trades = generate_trades_parallel(
+			&mut partitions,
+			&grid_request,
+			&grid_offer,
+			MatchingMode::Price,
+		);
+
+		assert_eq!(trades.len(), 1);
+		assert_eq!(trades[0].buyer, "buyer_1");
+		assert_eq!(trades[0].seller, "seller_1");
+	}
+
+	#[test]
+	fn all_requested_and_offered_amounts_are_traded() {
+		let mut partitions = vec![
+			MarketPartition {
+				zone: "mg_1".into(),
+				requests: vec![
+					EnergyRequest {
+						amount: 10,
+						price: 1.9,
+						buyer: "buyer_1".into(),
+						..Default::default()
+					},
+					EnergyRequest {
+						amount: 10,
+						price: 2.4,
+						buyer: "buyer_2".into(),
+						..Default::default()
+					},
+				],
+				offers: vec![EnergyOffer {
+					amount: 5,
+					price: 2.3,
+					seller: "seller_1".into(),
+					..Default::default()
+				}],
+			},
+			MarketPartition {
+				zone: "mg_2".into(),
+				requests: vec![EnergyRequest {
+					amount: 10,
+					price: 2.8,
+					buyer: "buyer_3".into(),
+					..Default::default()
+				}],
+				offers: vec![
+					EnergyOffer {
+						amount: 10,
+						price: 2.0,
+						seller: "seller_2".into(),
+						..Default::default()
+					},
+					EnergyOffer {
+						amount: 10,
+						price: 2.7,
+						seller: "seller_3".into(),
+						..Default::default()
+					},
+				],
+			},
+		];
+		let (grid_request, grid_offer) = grid();
+		let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+
+		let trades = generate_trades_parallel_in(
+			&pool,
+			&mut partitions,
+			&grid_request,
+			&grid_offer,
+			MatchingMode::Price,
+		);
+
+		let requested: u64 =
+			partitions.iter().flat_map(|p| &p.requests).map(|r| r.amount as u64).sum();
+		let offered: u64 = partitions.iter().flat_map(|p| &p.offers).map(|o| o.amount as u64).sum();
+		let bought: u64 =
+			trades.iter().filter(|t| t.buyer.ne("grid")).map(|t| t.amount as u64).sum();
+		let sold: u64 =
+			trades.iter().filter(|t| t.seller.ne("grid")).map(|t| t.amount as u64).sum();
+		assert_eq!(bought, requested);
+		assert_eq!(sold, offered);
+	}
+
+	#[test]
+	fn residuals_are_matched_in_the_given_mode() {
+		let offer = |seller: &str, carbon_intensity| EnergyOffer {
+			amount: 10,
+			price: 2.0,
+			seller: seller.into(),
+			carbon_intensity,
+			..Default::default()
+		};
+		let partitions = vec![
+			MarketPartition {
+				zone: "mg_1".into(),
+				requests: vec![EnergyRequest {
+					amount: 10,
+					price: 2.5,
+					buyer: "buyer_1".into(),
+					..Default::default()
+				}],
+				offers: vec![],
+			},
+			MarketPartition {
+				zone: "mg_2".into(),
+				requests: vec![],
+				offers: vec![offer("seller_1", 400.0), offer("seller_2", 20.0)],
+			},
+		];
+		let (grid_request, grid_offer) = grid();
+
+		for (mode, seller) in
+			[(MatchingMode::Price, "seller_1"), (MatchingMode::LowCarbon, "seller_2")]
+		{
+			let trades =
+				generate_trades_parallel(&mut partitions.clone(), &grid_request, &grid_offer, mode);
+
+			assert_eq!(trades[0].seller, seller);
+		}
+	}
+}
@@ -0,0 +1,316 @@
+//! Loading recorded order books and replaying them through the matcher.
+//!
+//! Order dumps are either a JSON array, as written by the benches, JSON Lines with one order per
+//! line, or CSV with a header row. A replayed round can be compared with a stored golden trade
+//! list, so changes to the algorithm show their effect on real snapshots.
+
+use std::{
+	collections::HashMap,
+	fmt,
+	fs::File,
+	io::{self, BufRead, BufReader, BufWriter, Read, Write},
+	path::{Path, PathBuf},
+	str::FromStr,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::energy_trade_matching::{generate_trades, EnergyOffer, EnergyRequest, Trade};
+
+#[derive(Debug)]
+pub enum ReplayError {
+	Io(io::Error),
+	Json(serde_json::Error),
+	Csv(csv::Error),
+	/// The format of the file could not be told from its extension
+	UnknownFormat(PathBuf),
+}
+
+impl fmt::Display for ReplayError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ReplayError::Io(e) => write!(f, "I/O error: {}", e),
+			ReplayError::Json(e) => write!(f, "Invalid JSON: {}", e),
+			ReplayError::Csv(e) => write!(f, "Invalid CSV: {}", e),
+			ReplayError::UnknownFormat(path) =>
+				write!(f, "Unknown order dump format: {}", path.display()),
+		}
+	}
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+	fn from(e: io::Error) -> Self {
+		ReplayError::Io(e)
+	}
+}
+
+impl From<serde_json::Error> for ReplayError {
+	fn from(e: serde_json::Error) -> Self {
+		ReplayError::Json(e)
+	}
+}
+
+impl From<csv::Error> for ReplayError {
+	fn from(e: csv::Error) -> Self {
+		ReplayError::Csv(e)
+	}
+}
+
+/// Layout of a dump of orders or trades
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+	/// A single JSON array
+	Json,
+	/// One JSON object per line
+	JsonLines,
+	/// One record per row, with a header row naming the fields
+	Csv,
+}
+
+impl DumpFormat {
+	/// Tell the format from the extension of the path, `.json`, `.jsonl` or `.csv`.
+	pub fn from_path(path: &Path) -> Result<Self, ReplayError> {
+		path.extension()
+			.and_then(|e| e.to_str())
+			.and_then(|e| e.parse().ok())
+			.ok_or_else(|| ReplayError::UnknownFormat(path.to_path_buf()))
+	}
+}
+
+impl FromStr for DumpFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"json" => Ok(DumpFormat::Json),
+			"jsonl" | "ndjson" => Ok(DumpFormat::JsonLines),
+			"csv" => Ok(DumpFormat::Csv),
+			_ => Err(format!("Unknown format '{}', expected json, jsonl or csv", s)),
+		}
+	}
+}
+
+/// Read requests, offers or trades in the given format.
+pub fn read_dump<T: DeserializeOwned, R: Read>(
+	reader: R,
+	format: DumpFormat,
+) -> Result<Vec<T>, ReplayError> {
+	match format {
+		DumpFormat::Json => Ok(serde_json::from_reader(reader)?),
+		DumpFormat::JsonLines => {
+			let mut items = Vec::new();
+			for line in BufReader::new(reader).lines() {
+				let line = line?;
+				if !line.trim().is_empty() {
+					items.push(serde_json::from_str(&line)?);
+				}
+			}
+			Ok(items)
+		},
+		DumpFormat::Csv =>
+			Ok(csv::Reader::from_reader(reader).deserialize().collect::<Result<_, _>>()?),
+	}
+}
+
+/// Write requests, offers or trades in the given format.
+pub fn write_dump<T: Serialize, W: Write>(
+	writer: W,
+	items: &[T],
+	format: DumpFormat,
+) -> Result<(), ReplayError> {
+	let mut writer = BufWriter::new(writer);
+	match format {
+		DumpFormat::Json => serde_json::to_writer_pretty(&mut writer, items)?,
+		DumpFormat::JsonLines =>
+			for item in items {
+				serde_json::to_writer(&mut writer, item)?;
+				writer.write_all(b"\n")?;
+			},
+		DumpFormat::Csv => {
+			let mut csv_writer = csv::Writer::from_writer(&mut writer);
+			for item in items {
+				csv_writer.serialize(item)?;
+			}
+			csv_writer.flush()?;
+		},
+	}
+	writer.flush()?;
+	Ok(())
+}
+
+/// Load a dump from a file, telling the format from its extension.
+pub fn load_dump<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<Vec<T>, ReplayError> {
+	let path = path.as_ref();
+	read_dump(File::open(path)?, DumpFormat::from_path(path)?)
+}
+
+/// Save a dump to a file, telling the format from its extension.
+pub fn save_dump<T: Serialize>(path: impl AsRef<Path>, items: &[T]) -> Result<(), ReplayError> {
+	let path = path.as_ref();
+	let format = DumpFormat::from_path(path)?;
+	write_dump(File::create(path)?, items, format)
+}
+
+/// The order books and grid prices of a single recorded round
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedRound {
+	pub requests: Vec<EnergyRequest>,
+	pub offers: Vec<EnergyOffer>,
+	pub grid_request: EnergyRequest,
+	pub grid_offer: EnergyOffer,
+}
+
+impl RecordedRound {
+	pub fn load(
+		requests_path: impl AsRef<Path>,
+		offers_path: impl AsRef<Path>,
+		grid_request: EnergyRequest,
+		grid_offer: EnergyOffer,
+	) -> Result<Self, ReplayError> {
+		Ok(RecordedRound {
+			requests: load_dump(requests_path)?,
+			offers: load_dump(offers_path)?,
+			grid_request,
+			grid_offer,
+		})
+	}
+
+	/// Run the round through [`generate_trades`], leaving the recording untouched.
+	pub fn replay(&self) -> Vec<Trade> {
+		let mut requests = self.requests.clone();
+		let mut offers = self.offers.clone();
+		generate_trades(&mut requests, &mut offers, &self.grid_request, &self.grid_offer)
+	}
+}
+
+/// Difference between an expected and an actual trade list, ignoring order
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TradeDiff {
+	/// Expected trades which were not formed
+	pub missing: Vec<Trade>,
+	/// Formed trades which were not expected
+	pub unexpected: Vec<Trade>,
+}
+
+impl TradeDiff {
+	pub fn is_empty(&self) -> bool {
+		self.missing.is_empty() && self.unexpected.is_empty()
+	}
+}
+
+impl fmt::Display for TradeDiff {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "{} missing, {} unexpected trades", self.missing.len(), self.unexpected.len())?;
+		for t in &self.missing {
+			writeln!(f, "- {} -> {}: {} @ {}", t.seller, t.buyer, t.amount, t.price)?;
+		}
+		for t in &self.unexpected {
+			writeln!(f, "+ {} -> {}: {} @ {}", t.seller, t.buyer, t.amount, t.price)?;
+		}
+		Ok(())
+	}
+}
+
+/// Compare two trade lists as multisets, with prices compared bit for bit.
+pub fn diff_trades(expected: &[Trade], actual: &[Trade]) -> TradeDiff {
+	fn key(t: &Trade) -> (u16, u32, &str, &str) {
+		(t.amount, t.price.to_bits(), &t.buyer, &t.seller)
+	}
+
+	let mut counts = HashMap::<_, i64>::new();
+	for t in expected {
+		*counts.entry(key(t)).or_default() += 1;
+	}
+	for t in actual {
+		*counts.entry(key(t)).or_default() -= 1;
+	}
+
+	let mut diff = TradeDiff::default();
+	for t in expected {
+		if let Some(count) = counts.get_mut(&key(t)) {
+			if *count > 0 {
+				*count -= 1;
+				diff.missing.push(t.clone());
+			}
+		}
+	}
+	for t in actual {
+		if let Some(count) = counts.get_mut(&key(t)) {
+			if *count < 0 {
+				*count += 1;
+				diff.unexpected.push(t.clone());
+			}
+		}
+	}
+	diff
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn trade(amount: u16, buyer: &str) -> Trade {
+		Trade {
+			amount,
+			price: 2.0,
+			buyer: buyer.into(),
+			seller: "seller_1".into(),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn json_and_json_lines_dumps_are_read_alike() {
+		let json = r#"[{"amount":10,"price":2.1,"buyer":"buyer_1"},{"amount":5,"price":1.9,"buyer":"buyer_2"}]"#;
+		let json_lines = "{\"amount\":10,\"price\":2.1,\"buyer\":\"buyer_1\"}\n\n{\"amount\":5,\"price\":1.9,\"buyer\":\"buyer_2\"}\n";
+
+		let from_json: Vec<EnergyRequest> = read_dump(json.as_bytes(), DumpFormat::Json).unwrap();
+		let from_json_lines: Vec<EnergyRequest> =
+			read_dump(json_lines.as_bytes(), DumpFormat::JsonLines).unwrap();
+
+		assert_eq!(from_json.len(), 2);
+		assert_eq!(
+			serde_json::to_string(&from_json).unwrap(),
+			serde_json::to_string(&from_json_lines).unwrap()
+		);
+	}
+
+	#[test]
+	fn csv_dumps_round_trip() {
+		let offers = vec![
+			EnergyOffer { amount: 10, price: 2.1, seller: "seller_1".into(), ..Default::default() },
+			EnergyOffer { amount: 5, price: 1.9, seller: "seller_2".into(), ..Default::default() },
+		];
+		let mut dump = Vec::new();
+
+		write_dump(&mut dump, &offers, DumpFormat::Csv).unwrap();
+		let read: Vec<EnergyOffer> = read_dump(dump.as_slice(), DumpFormat::Csv).unwrap();
+
+		assert!(String::from_utf8(dump).unwrap().starts_with("amount,price,seller,source,carbon_intensity\n"));
+		assert_eq!(serde_json::to_string(&read).unwrap(), serde_json::to_string(&offers).unwrap());
+	}
+
+	#[test]
+	fn format_is_told_from_extension() {
+		assert_eq!(DumpFormat::from_path(Path::new("offers-1.json")).unwrap(), DumpFormat::Json);
+		assert_eq!(DumpFormat::from_path(Path::new("offers.jsonl")).unwrap(), DumpFormat::JsonLines);
+		assert_eq!(DumpFormat::from_path(Path::new("offers.csv")).unwrap(), DumpFormat::Csv);
+		assert!(DumpFormat::from_path(Path::new("offers.txt")).is_err());
+	}
+
+	#[test]
+	fn diff_ignores_order_but_counts_duplicates() {
+		let expected = vec![trade(10, "buyer_1"), trade(5, "buyer_2"), trade(5, "buyer_2")];
+		let actual = vec![trade(5, "buyer_2"), trade(10, "buyer_1"), trade(7, "buyer_3")];
+
+		let diff = diff_trades(&expected, &actual);
+
+		assert_eq!(diff.missing.len(), 1);
+		assert_eq!(diff.missing[0].buyer, "buyer_2");
+		assert_eq!(diff.unexpected.len(), 1);
+		assert_eq!(diff.unexpected[0].buyer, "buyer_3");
+		assert!(diff_trades(&expected, &expected).is_empty());
+	}
+}
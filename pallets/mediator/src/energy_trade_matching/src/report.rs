@@ -0,0 +1,268 @@
+//! Summary of a cleared round.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::energy_trade_matching::{EnergyOffer, EnergyRequest, Trade};
+
+/// Volumes and prices of a single cleared round
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RoundReport {
+	pub requests: usize,
+	pub offers: usize,
+	pub trades: usize,
+	pub requested_amount: u64,
+	pub offered_amount: u64,
+	/// Amount traded between peers
+	pub peer_to_peer_amount: u64,
+	/// Amount bought from the grid
+	pub grid_import_amount: u64,
+	/// Amount sold to the grid
+	pub grid_export_amount: u64,
+	/// Amount requested but not bought, as buyers ran out of budget
+	#[serde(default)]
+	pub unserved_amount: u64,
+	/// Amount times price, summed over all trades
+	pub total_value: f64,
+	/// Volume weighted average price of the peer-to-peer trades
+	pub average_peer_to_peer_price: Option<f32>,
+	pub min_price: Option<f32>,
+	pub max_price: Option<f32>,
+	/// Grams of CO2 emitted for the energy delivered to buyers, from peers and the grid
+	pub total_emissions: f64,
+	/// Volume weighted average carbon intensity of the energy delivered to buyers
+	pub average_carbon_intensity: Option<f32>,
+	/// Grams of CO2 emitted for the energy sold to the grid, which is not consumed in the round
+	#[serde(default)]
+	pub grid_export_emissions: f64,
+}
+
+impl RoundReport {
+	/// Summarise the trades formed from the given orders, telling grid trades apart by the ids of
+	/// the grid's request and offer.
+	pub fn new(
+		requests: &[EnergyRequest],
+		offers: &[EnergyOffer],
+		trades: &[Trade],
+		grid_request: &EnergyRequest,
+		grid_offer: &EnergyOffer,
+	) -> Self {
+		let mut report = RoundReport {
+			requests: requests.len(),
+			offers: offers.len(),
+			trades: trades.len(),
+			requested_amount: requests.iter().map(|r| r.amount as u64).sum(),
+			offered_amount: offers.iter().map(|o| o.amount as u64).sum(),
+			..Default::default()
+		};
+
+		let mut peer_to_peer_value = 0.0;
+		for t in trades {
+			if t.seller == grid_offer.seller {
+				report.grid_import_amount += t.amount as u64;
+				report.total_emissions += t.emissions();
+			} else if t.buyer == grid_request.buyer {
+				report.grid_export_amount += t.amount as u64;
+				report.grid_export_emissions += t.emissions();
+			} else {
+				report.peer_to_peer_amount += t.amount as u64;
+				peer_to_peer_value += t.amount as f64 * t.price as f64;
+				report.total_emissions += t.emissions();
+			}
+			report.total_value += t.amount as f64 * t.price as f64;
+			report.min_price = Some(report.min_price.map_or(t.price, |p| p.min(t.price)));
+			report.max_price = Some(report.max_price.map_or(t.price, |p| p.max(t.price)));
+		}
+		report.unserved_amount = report
+			.requested_amount
+			.saturating_sub(report.peer_to_peer_amount + report.grid_import_amount);
+		if report.peer_to_peer_amount > 0 {
+			report.average_peer_to_peer_price =
+				Some((peer_to_peer_value / report.peer_to_peer_amount as f64) as f32);
+		}
+		let delivered_amount = report.peer_to_peer_amount + report.grid_import_amount;
+		if delivered_amount > 0 {
+			report.average_carbon_intensity =
+				Some((report.total_emissions / delivered_amount as f64) as f32);
+		}
+
+		report
+	}
+}
+
+/// The requests not fully served, each with the amount left unserved.
+///
+/// What a buyer bought is counted towards their requests in the order given.
+pub fn unserved_requests(requests: &[EnergyRequest], trades: &[Trade]) -> Vec<EnergyRequest> {
+	let mut bought = HashMap::<&str, u64>::new();
+	for t in trades {
+		*bought.entry(&t.buyer).or_default() += t.amount as u64;
+	}
+
+	requests
+		.iter()
+		.filter_map(|r| {
+			let bought = bought.entry(&r.buyer).or_default();
+			let served = (*bought).min(r.amount as u64);
+			*bought -= served;
+			(served < r.amount as u64)
+				.then(|| EnergyRequest { amount: r.amount - served as u16, ..r.clone() })
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::energy_trade_matching::generate_trades;
+
+	#[test]
+	fn volumes_are_split_between_peers_and_grid() {
+		let mut requests = vec![
+			EnergyRequest { amount: 10, price: 1.9, buyer: "buyer_1".into(), ..Default::default() },
+			EnergyRequest { amount: 10, price: 2.4, buyer: "buyer_2".into(), ..Default::default() },
+		];
+		let mut offers = vec![
+			EnergyOffer { amount: 15, price: 2.0, seller: "seller_1".into(), ..Default::default() },
+			EnergyOffer { amount: 4, price: 2.8, seller: "seller_2".into(), ..Default::default() },
+		];
+		let grid_request = EnergyRequest {
+			amount: 10,
+			price: 1.5,
+			buyer: "grid".into(),
+			..Default::default()
+		};
+		let grid_offer = EnergyOffer {
+			amount: 10,
+			price: 2.6,
+			seller: "grid".into(),
+			..Default::default()
+		};
+		let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
+
+		let report = RoundReport::new(&requests, &offers, &trades, &grid_request, &grid_offer);
+
+		assert_eq!(report.requested_amount, 20);
+		assert_eq!(report.offered_amount, 19);
+		assert_eq!(report.peer_to_peer_amount, 10);
+		assert_eq!(report.grid_import_amount, 10);
+		assert_eq!(report.grid_export_amount, 9);
+		assert_eq!(report.average_peer_to_peer_price, Some(2.2));
+		assert_eq!(report.min_price, Some(1.5));
+		assert_eq!(report.max_price, Some(2.6));
+	}
+
+	#[test]
+	fn emissions_include_energy_bought_from_the_grid() {
+		let mut requests = vec![EnergyRequest {
+			amount: 10,
+			price: 2.4,
+			buyer: "buyer_1".into(),
+			..Default::default()
+		}];
+		let mut offers = vec![EnergyOffer {
+			amount: 4,
+			price: 2.0,
+			seller: "seller_1".into(),
+			carbon_intensity: 50.0,
+			..Default::default()
+		}];
+		let grid_request = EnergyRequest {
+			amount: 10,
+			price: 1.5,
+			buyer: "grid".into(),
+			..Default::default()
+		};
+		let grid_offer = EnergyOffer {
+			amount: 10,
+			price: 2.6,
+			seller: "grid".into(),
+			carbon_intensity: 400.0,
+			..Default::default()
+		};
+		let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
+
+		let report = RoundReport::new(&requests, &offers, &trades, &grid_request, &grid_offer);
+
+		assert_eq!(report.total_emissions, 4.0 * 50.0 + 6.0 * 400.0);
+		assert_eq!(report.average_carbon_intensity, Some(260.0));
+	}
+
+	#[test]
+	fn emissions_of_energy_sold_to_the_grid_are_reported_apart() {
+		let mut requests = vec![EnergyRequest {
+			amount: 4,
+			price: 2.4,
+			buyer: "buyer_1".into(),
+			..Default::default()
+		}];
+		let mut offers = vec![EnergyOffer {
+			amount: 10,
+			price: 2.0,
+			seller: "seller_1".into(),
+			carbon_intensity: 50.0,
+			..Default::default()
+		}];
+		let grid_request = EnergyRequest {
+			amount: 10,
+			price: 1.5,
+			buyer: "grid".into(),
+			..Default::default()
+		};
+		let grid_offer = EnergyOffer {
+			amount: 10,
+			price: 2.6,
+			seller: "grid".into(),
+			carbon_intensity: 400.0,
+			..Default::default()
+		};
+		let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
+
+		let report = RoundReport::new(&requests, &offers, &trades, &grid_request, &grid_offer);
+
+		assert_eq!(report.grid_export_amount, 6);
+		assert_eq!(report.total_emissions, 4.0 * 50.0);
+		assert_eq!(report.average_carbon_intensity, Some(50.0));
+		assert_eq!(report.grid_export_emissions, 6.0 * 50.0);
+	}
+
+	#[test]
+	fn requests_beyond_the_budget_are_left_unserved() {
+		let mut requests = vec![EnergyRequest {
+			amount: 10,
+			price: 2.4,
+			buyer: "buyer_1".into(),
+			budget: Some(15.0),
+			..Default::default()
+		}];
+		let mut offers = vec![EnergyOffer {
+			amount: 4,
+			price: 2.0,
+			seller: "seller_1".into(),
+			..Default::default()
+		}];
+		let grid_request = EnergyRequest {
+			amount: 10,
+			price: 1.5,
+			buyer: "grid".into(),
+			..Default::default()
+		};
+		let grid_offer = EnergyOffer {
+			amount: 10,
+			price: 2.6,
+			seller: "grid".into(),
+			..Default::default()
+		};
+		let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
+
+		let report = RoundReport::new(&requests, &offers, &trades, &grid_request, &grid_offer);
+
+		// 4 units from the peer at 2.2 and 2 from the grid at 2.6 spend 14
+		assert_eq!(report.peer_to_peer_amount, 4);
+		assert_eq!(report.grid_import_amount, 2);
+		assert_eq!(report.unserved_amount, 4);
+		let unserved = unserved_requests(&requests, &trades);
+		assert_eq!((unserved[0].buyer.as_str(), unserved[0].amount), ("buyer_1", 4));
+	}
+}
@@ -0,0 +1,339 @@
+//! Synthetic, reproducible market scenarios.
+//!
+//! A scenario is generated from a [`ScenarioConfig`] and a seed, so the same configuration always
+//! yields the same order books. Scenarios can drive benches, tests and simulations alike.
+
+use std::{f32::consts::PI, fmt};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+
+use crate::energy_trade_matching::{EnergyOffer, EnergyRequest, MarketPartition};
+
+/// Distribution to draw amounts or prices from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Distribution {
+	Constant(f32),
+	/// Uniform over the half-open range `[min, max)`
+	Uniform {
+		min: f32,
+		max: f32,
+	},
+	Normal {
+		mean: f32,
+		std_dev: f32,
+	},
+}
+
+impl Distribution {
+	/// Whether the distribution can be sampled from: its parameters are finite, the range of a
+	/// uniform distribution is not empty and the standard deviation is not negative.
+	pub fn is_valid(&self) -> bool {
+		match *self {
+			Distribution::Constant(value) => value.is_finite(),
+			Distribution::Uniform { min, max } => min.is_finite() && max.is_finite() && min < max,
+			Distribution::Normal { mean, std_dev } => {
+				mean.is_finite() && std_dev.is_finite() && std_dev >= 0.0
+			},
+		}
+	}
+
+	/// Check the distribution of the named field with [`Distribution::is_valid`].
+	pub fn validate(&self, field: impl Into<String>) -> Result<(), ScenarioError> {
+		if self.is_valid() {
+			Ok(())
+		} else {
+			Err(ScenarioError::InvalidDistribution {
+				field: field.into(),
+				distribution: self.clone(),
+			})
+		}
+	}
+
+	/// Draw a value. Panics if the distribution is not valid.
+	pub fn sample<R: Rng>(&self, rng: &mut R) -> f32 {
+		match *self {
+			Distribution::Constant(value) => value,
+			Distribution::Uniform { min, max } => rng.gen_range(min..max),
+			Distribution::Normal { mean, std_dev } => rng.sample(
+				Normal::new(mean, std_dev).expect("Standard deviation is finite and non-negative"),
+			),
+		}
+	}
+}
+
+/// Why a configuration can not be used
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScenarioError {
+	/// The distribution of the named field can not be sampled from
+	InvalidDistribution { field: String, distribution: Distribution },
+}
+
+impl fmt::Display for ScenarioError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			ScenarioError::InvalidDistribution { field, distribution } => {
+				write!(f, "Invalid distribution of {}: {:?}", field, distribution)
+			},
+		}
+	}
+}
+
+impl std::error::Error for ScenarioError {}
+
+/// Shape of production or consumption over a day, scaling sampled amounts to the hour of the day
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DailyProfile {
+	/// The same amount at any hour
+	Flat,
+	/// Photovoltaic generation, a sine arc between sunrise and sunset peaking at solar noon
+	Pv { sunrise: f32, sunset: f32 },
+	/// Household consumption with a base load, a morning peak and a larger evening peak
+	ResidentialLoad,
+}
+
+impl DailyProfile {
+	/// The fraction of the peak amount at the given hour of the day, between 0 and 1.
+	pub fn factor(&self, hour: f32) -> f32 {
+		let hour = hour.rem_euclid(24.0);
+		match *self {
+			DailyProfile::Flat => 1.0,
+			DailyProfile::Pv { sunrise, sunset } => {
+				if hour <= sunrise || hour >= sunset {
+					0.0
+				} else {
+					(PI * (hour - sunrise) / (sunset - sunrise)).sin()
+				}
+			},
+			DailyProfile::ResidentialLoad => {
+				let morning = 0.4 * (-((hour - 7.5) / 1.5).powi(2)).exp();
+				let evening = 0.7 * (-((hour - 19.0) / 2.0).powi(2)).exp();
+				(0.3 + morning + evening).min(1.0)
+			},
+		}
+	}
+}
+
+/// Configuration of a synthetic market
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScenarioConfig {
+	pub seed: u64,
+	/// Participants only submitting requests
+	pub consumers: u32,
+	/// Participants only submitting offers
+	pub producers: u32,
+	/// Participants submitting either a request or an offer, depending on their net position
+	pub prosumers: u32,
+	/// Number of microgrids participants are spread over, round-robin
+	pub microgrids: u32,
+	/// Peak consumption of a participant
+	pub load_amount: Distribution,
+	/// Peak generation of a participant
+	pub generation_amount: Distribution,
+	pub request_price: Distribution,
+	pub offer_price: Distribution,
+	pub load_profile: DailyProfile,
+	pub generation_profile: DailyProfile,
+	/// Hour of the day the market is cleared for
+	pub hour: f32,
+	/// Price at which the grid buys energy
+	pub grid_request_price: f32,
+	/// Price at which the grid sells energy
+	pub grid_offer_price: f32,
+}
+
+impl Default for ScenarioConfig {
+	fn default() -> Self {
+		ScenarioConfig {
+			seed: 0,
+			consumers: 0,
+			producers: 0,
+			prosumers: 0,
+			microgrids: 1,
+			load_amount: Distribution::Uniform { min: 200.0, max: 1000.0 },
+			generation_amount: Distribution::Uniform { min: 200.0, max: 1000.0 },
+			request_price: Distribution::Uniform { min: 1.8, max: 3.5 },
+			offer_price: Distribution::Uniform { min: 2.0, max: 4.0 },
+			load_profile: DailyProfile::Flat,
+			generation_profile: DailyProfile::Flat,
+			hour: 12.0,
+			grid_request_price: 1.5,
+			grid_offer_price: 2.6,
+		}
+	}
+}
+
+impl ScenarioConfig {
+	/// Check that every distribution of the configuration can be sampled from.
+	pub fn validate(&self) -> Result<(), ScenarioError> {
+		self.load_amount.validate("load_amount")?;
+		self.generation_amount.validate("generation_amount")?;
+		self.request_price.validate("request_price")?;
+		self.offer_price.validate("offer_price")
+	}
+}
+
+/// A generated market, with the orders of each microgrid and the grid's request and offer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Scenario {
+	pub microgrids: Vec<MarketPartition>,
+	pub grid_request: EnergyRequest,
+	pub grid_offer: EnergyOffer,
+}
+
+impl Scenario {
+	/// Generate the market of the configuration, if it is valid.
+	pub fn generate(config: &ScenarioConfig) -> Result<Self, ScenarioError> {
+		config.validate()?;
+		let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+		let mut microgrids: Vec<MarketPartition> = (0..config.microgrids.max(1))
+			.map(|mg| MarketPartition { zone: format!("mg_{}", mg), ..Default::default() })
+			.collect();
+		let microgrid_count = microgrids.len();
+		let load_factor = config.load_profile.factor(config.hour);
+		let generation_factor = config.generation_profile.factor(config.hour);
+
+		let participants = (0..config.consumers)
+			.map(|i| (format!("consumer_{}", i), true, false))
+			.chain((0..config.producers).map(|i| (format!("producer_{}", i), false, true)))
+			.chain((0..config.prosumers).map(|i| (format!("prosumer_{}", i), true, true)));
+
+		for (index, (participant, consumes, produces)) in participants.enumerate() {
+			let load =
+				if consumes { config.load_amount.sample(&mut rng) * load_factor } else { 0.0 };
+			let generation = if produces {
+				config.generation_amount.sample(&mut rng) * generation_factor
+			} else {
+				0.0
+			};
+			let microgrid = &mut microgrids[index % microgrid_count];
+
+			let surplus = to_amount(generation - load);
+			let deficit = to_amount(load - generation);
+			if surplus > 0 {
+				let price = config.offer_price.sample(&mut rng);
+				microgrid.offers.push(EnergyOffer {
+					amount: surplus,
+					price,
+					seller: participant,
+					..Default::default()
+				});
+			} else if deficit > 0 {
+				let price = config.request_price.sample(&mut rng);
+				microgrid.requests.push(EnergyRequest {
+					amount: deficit,
+					price,
+					buyer: participant,
+					..Default::default()
+				});
+			}
+		}
+
+		Ok(Scenario {
+			microgrids,
+			grid_request: EnergyRequest {
+				amount: 20,
+				price: config.grid_request_price,
+				buyer: String::from("Grid_Buyer"),
+				..Default::default()
+			},
+			grid_offer: EnergyOffer {
+				amount: 20,
+				price: config.grid_offer_price,
+				seller: String::from("Grid_Seller"),
+				..Default::default()
+			},
+		})
+	}
+
+	/// All requests and offers of the scenario, regardless of microgrid.
+	pub fn orders(&self) -> (Vec<EnergyRequest>, Vec<EnergyOffer>) {
+		let requests = self.microgrids.iter().flat_map(|mg| mg.requests.iter().cloned()).collect();
+		let offers = self.microgrids.iter().flat_map(|mg| mg.offers.iter().cloned()).collect();
+		(requests, offers)
+	}
+}
+
+fn to_amount(value: f32) -> u16 {
+	value.round().clamp(0.0, u16::MAX as f32) as u16
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(seed: u64) -> ScenarioConfig {
+		ScenarioConfig {
+			seed,
+			consumers: 20,
+			producers: 20,
+			prosumers: 20,
+			microgrids: 4,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn same_seed_generates_the_same_market() {
+		let first = serde_json::to_string(&Scenario::generate(&config(7)).unwrap()).unwrap();
+		let second = serde_json::to_string(&Scenario::generate(&config(7)).unwrap()).unwrap();
+		let other = serde_json::to_string(&Scenario::generate(&config(8)).unwrap()).unwrap();
+
+		assert_eq!(first, second);
+		assert_ne!(first, other);
+	}
+
+	#[test]
+	fn participants_are_spread_over_microgrids_with_unique_ids() {
+		let scenario = Scenario::generate(&config(1)).unwrap();
+		let (requests, offers) = scenario.orders();
+
+		assert_eq!(scenario.microgrids.len(), 4);
+		assert!(scenario.microgrids.iter().all(|mg| mg.requests.len() + mg.offers.len() == 15));
+		assert_eq!(requests.iter().filter(|r| r.buyer.starts_with("consumer")).count(), 20);
+		assert_eq!(offers.iter().filter(|o| o.seller.starts_with("producer")).count(), 20);
+
+		let mut ids: Vec<&String> = requests
+			.iter()
+			.map(|r| &r.buyer)
+			.chain(offers.iter().map(|o| &o.seller))
+			.collect();
+		ids.sort();
+		ids.dedup();
+		assert_eq!(ids.len(), requests.len() + offers.len());
+	}
+
+	#[test]
+	fn pv_producers_do_not_offer_at_night() {
+		let scenario = Scenario::generate(&ScenarioConfig {
+			generation_profile: DailyProfile::Pv { sunrise: 6.0, sunset: 20.0 },
+			load_profile: DailyProfile::ResidentialLoad,
+			hour: 23.0,
+			..config(3)
+		})
+		.unwrap();
+		let (requests, offers) = scenario.orders();
+
+		assert!(offers.is_empty());
+		assert_eq!(requests.len(), 40);
+	}
+
+	#[test]
+	fn distributions_that_can_not_be_sampled_from_are_rejected() {
+		let invalid = [
+			Distribution::Uniform { min: 3.0, max: 3.0 },
+			Distribution::Uniform { min: 1.0, max: f32::INFINITY },
+			Distribution::Normal { mean: 2.0, std_dev: -1.0 },
+			Distribution::Normal { mean: 2.0, std_dev: f32::NAN },
+		];
+
+		for distribution in invalid {
+			let config = ScenarioConfig { offer_price: distribution, ..config(1) };
+			let ScenarioError::InvalidDistribution { field, .. } =
+				Scenario::generate(&config).unwrap_err();
+			assert_eq!(field, "offer_price");
+		}
+	}
+}
@@ -0,0 +1,114 @@
+//! Per-participant settlement of a cleared round.
+//!
+//! Billing needs the net position of each participant rather than every single trade, so the
+//! trades of a round are netted into one statement per participant.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::energy_trade_matching::{EnergyOffer, EnergyRequest, Trade};
+
+/// Energy and money position of a single participant over all their trades
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Statement {
+	pub participant: String,
+	pub energy_bought: u64,
+	pub energy_sold: u64,
+	pub amount_paid: f64,
+	pub amount_received: f64,
+	/// Paid for energy bought from the grid, included in `amount_paid`
+	pub grid_costs: f64,
+	/// Energy bought minus energy sold
+	pub net_energy: i64,
+	/// Amount received minus amount paid
+	pub net_balance: f64,
+	/// Grams of CO2 emitted for the energy bought
+	pub emissions: f64,
+}
+
+/// Net the trades into one statement per participant, ordered by participant.
+///
+/// The grid, identified by the ids of its request and offer, does not get a statement.
+pub fn settle(
+	trades: &[Trade],
+	grid_request: &EnergyRequest,
+	grid_offer: &EnergyOffer,
+) -> Vec<Statement> {
+	let mut statements = BTreeMap::<&str, Statement>::new();
+	let is_grid = |id: &str| id == grid_request.buyer || id == grid_offer.seller;
+
+	for t in trades {
+		let value = t.amount as f64 * t.price as f64;
+
+		if !is_grid(&t.buyer) {
+			let buyer = statements.entry(&t.buyer).or_default();
+			buyer.energy_bought += t.amount as u64;
+			buyer.amount_paid += value;
+			buyer.emissions += t.emissions();
+			if t.seller == grid_offer.seller {
+				buyer.grid_costs += value;
+			}
+		}
+		if !is_grid(&t.seller) {
+			let seller = statements.entry(&t.seller).or_default();
+			seller.energy_sold += t.amount as u64;
+			seller.amount_received += value;
+		}
+	}
+
+	statements
+		.into_iter()
+		.map(|(participant, statement)| Statement {
+			participant: participant.to_owned(),
+			net_energy: statement.energy_bought as i64 - statement.energy_sold as i64,
+			net_balance: statement.amount_received - statement.amount_paid,
+			..statement
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn trade(amount: u16, price: f32, buyer: &str, seller: &str) -> Trade {
+		Trade { amount, price, buyer: buyer.into(), seller: seller.into(), ..Default::default() }
+	}
+
+	#[test]
+	fn trades_are_netted_per_participant() {
+		let grid_request = EnergyRequest {
+			amount: 10,
+			price: 1.5,
+			buyer: "grid".into(),
+			..Default::default()
+		};
+		let grid_offer = EnergyOffer {
+			amount: 10,
+			price: 3.0,
+			seller: "grid".into(),
+			..Default::default()
+		};
+		let trades = vec![
+			trade(10, 2.0, "prosumer_1", "prosumer_2"),
+			trade(5, 3.0, "prosumer_1", "grid"),
+			trade(4, 2.5, "prosumer_2", "prosumer_1"),
+			trade(6, 1.5, "grid", "prosumer_2"),
+		];
+
+		let statements = settle(&trades, &grid_request, &grid_offer);
+
+		assert_eq!(statements.len(), 2);
+		let first = &statements[0];
+		assert_eq!(first.participant, "prosumer_1");
+		assert_eq!((first.energy_bought, first.energy_sold, first.net_energy), (15, 4, 11));
+		assert_eq!((first.amount_paid, first.amount_received, first.grid_costs), (35.0, 10.0, 15.0));
+		assert_eq!(first.net_balance, -25.0);
+		let second = &statements[1];
+		assert_eq!(second.participant, "prosumer_2");
+		assert_eq!((second.energy_bought, second.energy_sold, second.net_energy), (4, 16, -12));
+		assert_eq!(second.net_balance, 19.0);
+		assert_eq!(second.grid_costs, 0.0);
+	}
+}
@@ -0,0 +1,429 @@
+//! Multi-round market simulation with bidding agents.
+//!
+//! Every round each agent places at most one order, the orders are cleared with
+//! [`generate_trades`], and agents learn from the outcome before the next round. Profits per agent
+//! and prices per round are recorded, so market rules can be evaluated before changing the pallet.
+
+use std::{collections::HashMap, path::Path};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	energy_trade_matching::{generate_trades, EnergyOffer, EnergyRequest, Trade},
+	replay::{save_dump, ReplayError},
+	report::RoundReport,
+	scenario::{Distribution, ScenarioError},
+};
+
+/// Whether a trading agent buys or sells energy
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+	Buyer,
+	Seller,
+}
+
+/// How a trading agent derives its order price from its limit price
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Strategy {
+	/// Random prices between the price bounds and the limit price, so that trades with peers never
+	/// make a loss. What is left unmatched still trades with the grid at the grid's prices, which
+	/// can be beyond the limit price.
+	ZeroIntelligence,
+	/// A constant relative markup on the limit price, or markdown for buyers
+	FixedMarkup { markup: f32 },
+	/// A markup which grows while the agent trades with peers and shrinks when it has to use the
+	/// grid
+	AdaptiveLearning { initial_markup: f32, learning_rate: f32 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AgentKind {
+	/// A consumer or producer with a limit price, the most it pays or the least it accepts
+	Trader { side: Side, limit_price: f32, amount: Distribution, strategy: Strategy },
+	/// A battery charging when prices are low and discharging when they are high
+	Battery { capacity: u16, power: u16, charge_below: f32, discharge_above: f32 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AgentConfig {
+	pub id: String,
+	pub kind: AgentKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SimulationConfig {
+	pub seed: u64,
+	pub rounds: u32,
+	/// Price at which the grid buys energy, also the lowest price zero-intelligence agents use
+	pub grid_request_price: f32,
+	/// Price at which the grid sells energy, also the highest price zero-intelligence agents use
+	pub grid_offer_price: f32,
+	pub agents: Vec<AgentConfig>,
+}
+
+/// Prices and volumes of a single round
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoundRecord {
+	pub round: u32,
+	pub average_price: Option<f32>,
+	pub min_price: Option<f32>,
+	pub max_price: Option<f32>,
+	pub peer_to_peer_amount: u64,
+	pub grid_import_amount: u64,
+	pub grid_export_amount: u64,
+}
+
+/// Outcome of a single round for a single agent
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AgentRecord {
+	pub round: u32,
+	pub agent: String,
+	pub order_price: Option<f32>,
+	pub traded_amount: u64,
+	pub profit: f64,
+	pub cumulative_profit: f64,
+	pub state_of_charge: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SimulationResult {
+	pub rounds: Vec<RoundRecord>,
+	pub agents: Vec<AgentRecord>,
+}
+
+impl SimulationResult {
+	/// Total profit of each agent over all rounds.
+	pub fn profits(&self) -> HashMap<&str, f64> {
+		let mut profits = HashMap::new();
+		for record in &self.agents {
+			*profits.entry(record.agent.as_str()).or_default() += record.profit;
+		}
+		profits
+	}
+
+	/// Write the time series to `rounds.csv` and `agents.csv` in the given directory.
+	pub fn export_csv(&self, directory: impl AsRef<Path>) -> Result<(), ReplayError> {
+		let directory = directory.as_ref();
+		save_dump(directory.join("rounds.csv"), &self.rounds)?;
+		save_dump(directory.join("agents.csv"), &self.agents)
+	}
+}
+
+struct Agent {
+	config: AgentConfig,
+	markup: f32,
+	state_of_charge: u16,
+	cumulative_profit: f64,
+}
+
+impl Agent {
+	fn new(config: AgentConfig) -> Self {
+		let markup = match &config.kind {
+			AgentKind::Trader { strategy: Strategy::FixedMarkup { markup }, .. } => *markup,
+			AgentKind::Trader {
+				strategy: Strategy::AdaptiveLearning { initial_markup, .. }, ..
+			} => *initial_markup,
+			_ => 0.0,
+		};
+		Agent { config, markup, state_of_charge: 0, cumulative_profit: 0.0 }
+	}
+
+	/// The order of the agent for the next round, if it wants to trade at all.
+	fn order(
+		&self,
+		rng: &mut ChaCha8Rng,
+		last_price: f32,
+		price_floor: f32,
+		price_cap: f32,
+	) -> Option<(Side, u16, f32)> {
+		match &self.config.kind {
+			AgentKind::Trader { side, limit_price, amount, strategy } => {
+				let amount = amount.sample(rng).round().clamp(0.0, u16::MAX as f32) as u16;
+				let price = match (strategy, side) {
+					(Strategy::ZeroIntelligence, Side::Buyer) if price_floor < *limit_price =>
+						rng.gen_range(price_floor..*limit_price),
+					(Strategy::ZeroIntelligence, Side::Seller) if *limit_price < price_cap =>
+						rng.gen_range(*limit_price..price_cap),
+					(Strategy::ZeroIntelligence, _) => *limit_price,
+					(_, Side::Buyer) => limit_price * (1.0 - self.markup),
+					(_, Side::Seller) => limit_price * (1.0 + self.markup),
+				};
+				(amount > 0).then_some((*side, amount, price))
+			},
+			AgentKind::Battery { capacity, power, charge_below, discharge_above } =>
+				if last_price <= *charge_below && self.state_of_charge < *capacity {
+					let headroom = capacity - self.state_of_charge;
+					Some((Side::Buyer, (*power).min(headroom), *charge_below))
+				} else if last_price >= *discharge_above && self.state_of_charge > 0 {
+					Some((Side::Seller, (*power).min(self.state_of_charge), *discharge_above))
+				} else {
+					None
+				},
+		}
+	}
+
+	/// Book the agent's trades of a round, and learn from them. Returns the profit of the round.
+	fn settle(&mut self, side: Side, trades: &[&Trade], used_grid: bool) -> f64 {
+		let traded: u64 = trades.iter().map(|t| t.amount as u64).sum();
+		let value: f64 = trades.iter().map(|t| t.amount as f64 * t.price as f64).sum();
+
+		let profit = match (&self.config.kind, side) {
+			(AgentKind::Trader { limit_price, .. }, Side::Buyer) =>
+				traded as f64 * *limit_price as f64 - value,
+			(AgentKind::Trader { limit_price, .. }, Side::Seller) =>
+				value - traded as f64 * *limit_price as f64,
+			(AgentKind::Battery { capacity, .. }, Side::Buyer) => {
+				self.state_of_charge =
+					(self.state_of_charge as u64 + traded).min(*capacity as u64) as u16;
+				-value
+			},
+			(AgentKind::Battery { .. }, Side::Seller) => {
+				self.state_of_charge = (self.state_of_charge as u64).saturating_sub(traded) as u16;
+				value
+			},
+		};
+
+		if let AgentKind::Trader {
+			strategy: Strategy::AdaptiveLearning { learning_rate, .. }, ..
+		} = &self.config.kind
+		{
+			self.markup = if used_grid {
+				(self.markup - learning_rate).max(0.0)
+			} else {
+				(self.markup + learning_rate).min(0.95)
+			};
+		}
+
+		self.cumulative_profit += profit;
+		profit
+	}
+}
+
+/// Runs rounds of the market with a fixed population of agents
+pub struct Simulation {
+	config: SimulationConfig,
+	agents: Vec<Agent>,
+	rng: ChaCha8Rng,
+	round: u32,
+	last_price: f32,
+}
+
+impl Simulation {
+	/// Set up the simulation, if the amounts of all traders can be sampled.
+	pub fn new(config: SimulationConfig) -> Result<Self, ScenarioError> {
+		for agent in &config.agents {
+			if let AgentKind::Trader { amount, .. } = &agent.kind {
+				amount.validate(format!("amount of {}", agent.id))?;
+			}
+		}
+		let agents = config.agents.iter().cloned().map(Agent::new).collect();
+		let rng = ChaCha8Rng::seed_from_u64(config.seed);
+		let last_price = (config.grid_request_price + config.grid_offer_price) / 2.0;
+		Ok(Simulation { config, agents, rng, round: 0, last_price })
+	}
+
+	/// Run all configured rounds.
+	pub fn run(&mut self) -> SimulationResult {
+		let mut result = SimulationResult::default();
+		for _ in 0..self.config.rounds {
+			let (round, agents) = self.step();
+			result.rounds.push(round);
+			result.agents.extend(agents);
+		}
+		result
+	}
+
+	/// Run a single round.
+	pub fn step(&mut self) -> (RoundRecord, Vec<AgentRecord>) {
+		let grid_request = EnergyRequest {
+			amount: 0,
+			price: self.config.grid_request_price,
+			buyer: String::from("grid"),
+			..Default::default()
+		};
+		let grid_offer = EnergyOffer {
+			amount: 0,
+			price: self.config.grid_offer_price,
+			seller: String::from("grid"),
+			..Default::default()
+		};
+
+		let orders: Vec<Option<(Side, u16, f32)>> = self
+			.agents
+			.iter()
+			.map(|agent| {
+				agent.order(
+					&mut self.rng,
+					self.last_price,
+					self.config.grid_request_price,
+					self.config.grid_offer_price,
+				)
+			})
+			.collect();
+
+		let mut requests = Vec::<EnergyRequest>::new();
+		let mut offers = Vec::<EnergyOffer>::new();
+		for (agent, order) in self.agents.iter().zip(&orders) {
+			let id = agent.config.id.clone();
+			match *order {
+				Some((Side::Buyer, amount, price)) =>
+					requests.push(EnergyRequest { amount, price, buyer: id, ..Default::default() }),
+				Some((Side::Seller, amount, price)) =>
+					offers.push(EnergyOffer { amount, price, seller: id, ..Default::default() }),
+				None => {},
+			}
+		}
+
+		let trades = generate_trades(&mut requests, &mut offers, &grid_request, &grid_offer);
+		let report = RoundReport::new(&requests, &offers, &trades, &grid_request, &grid_offer);
+
+		let mut trades_by_agent = HashMap::<&str, Vec<&Trade>>::new();
+		for t in &trades {
+			if t.buyer != grid_request.buyer {
+				trades_by_agent.entry(t.buyer.as_str()).or_default().push(t);
+			}
+			if t.seller != grid_offer.seller {
+				trades_by_agent.entry(t.seller.as_str()).or_default().push(t);
+			}
+		}
+
+		let mut agent_records = Vec::with_capacity(self.agents.len());
+		for (agent, order) in self.agents.iter_mut().zip(orders) {
+			let (profit, traded_amount) = match order {
+				Some((side, ..)) => {
+					let agent_trades =
+						trades_by_agent.get(agent.config.id.as_str()).cloned().unwrap_or_default();
+					let used_grid = agent_trades
+						.iter()
+						.any(|t| t.buyer == grid_request.buyer || t.seller == grid_offer.seller);
+					let traded = agent_trades.iter().map(|t| t.amount as u64).sum();
+					(agent.settle(side, &agent_trades, used_grid), traded)
+				},
+				None => (0.0, 0),
+			};
+			agent_records.push(AgentRecord {
+				round: self.round,
+				agent: agent.config.id.clone(),
+				order_price: order.map(|(_, _, price)| price),
+				traded_amount,
+				profit,
+				cumulative_profit: agent.cumulative_profit,
+				state_of_charge: match agent.config.kind {
+					AgentKind::Battery { .. } => Some(agent.state_of_charge),
+					AgentKind::Trader { .. } => None,
+				},
+			});
+		}
+
+		if let Some(price) = report.average_peer_to_peer_price {
+			self.last_price = price;
+		}
+		let round_record = RoundRecord {
+			round: self.round,
+			average_price: report.average_peer_to_peer_price,
+			min_price: report.min_price,
+			max_price: report.max_price,
+			peer_to_peer_amount: report.peer_to_peer_amount,
+			grid_import_amount: report.grid_import_amount,
+			grid_export_amount: report.grid_export_amount,
+		};
+		self.round += 1;
+
+		(round_record, agent_records)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn trader(id: &str, side: Side, limit_price: f32, strategy: Strategy) -> AgentConfig {
+		AgentConfig {
+			id: id.into(),
+			kind: AgentKind::Trader {
+				side,
+				limit_price,
+				amount: Distribution::Constant(10.0),
+				strategy,
+			},
+		}
+	}
+
+	fn config(agents: Vec<AgentConfig>) -> SimulationConfig {
+		SimulationConfig {
+			seed: 11,
+			rounds: 20,
+			grid_request_price: 1.5,
+			grid_offer_price: 3.0,
+			agents,
+		}
+	}
+
+	#[test]
+	fn same_seed_gives_the_same_time_series() {
+		let agents = vec![
+			trader("buyer_1", Side::Buyer, 2.8, Strategy::ZeroIntelligence),
+			trader("buyer_2", Side::Buyer, 2.5, Strategy::FixedMarkup { markup: 0.1 }),
+			trader("seller_1", Side::Seller, 1.8, Strategy::ZeroIntelligence),
+			trader("seller_2", Side::Seller, 2.0, Strategy::FixedMarkup { markup: 0.1 }),
+		];
+
+		let first = Simulation::new(config(agents.clone())).unwrap().run();
+		let second = Simulation::new(config(agents)).unwrap().run();
+
+		assert_eq!(first.rounds, second.rounds);
+		assert_eq!(first.agents, second.agents);
+		assert_eq!(first.rounds.len(), 20);
+		assert_eq!(first.agents.len(), 80);
+	}
+
+	#[test]
+	fn adaptive_seller_raises_its_price_while_it_sells_to_peers() {
+		let agents = vec![
+			trader("buyer_1", Side::Buyer, 2.9, Strategy::FixedMarkup { markup: 0.0 }),
+			trader(
+				"seller_1",
+				Side::Seller,
+				2.0,
+				Strategy::AdaptiveLearning { initial_markup: 0.0, learning_rate: 0.05 },
+			),
+		];
+
+		let result = Simulation::new(config(agents)).unwrap().run();
+		let seller: Vec<&AgentRecord> =
+			result.agents.iter().filter(|r| r.agent == "seller_1").collect();
+
+		assert!(seller.last().unwrap().order_price > seller.first().unwrap().order_price);
+		assert!(seller.iter().all(|r| r.order_price.unwrap() <= 2.9 + 0.1));
+		assert!(result.profits()["seller_1"] > 0.0);
+	}
+
+	#[test]
+	fn battery_charges_cheaply_and_stays_within_capacity() {
+		let agents = vec![
+			AgentConfig {
+				id: "battery_1".into(),
+				kind: AgentKind::Battery {
+					capacity: 25,
+					power: 10,
+					charge_below: 2.5,
+					discharge_above: 2.6,
+				},
+			},
+			trader("seller_1", Side::Seller, 1.0, Strategy::FixedMarkup { markup: 0.0 }),
+		];
+
+		let result = Simulation::new(config(agents)).unwrap().run();
+		let battery: Vec<u16> = result
+			.agents
+			.iter()
+			.filter(|r| r.agent == "battery_1")
+			.map(|r| r.state_of_charge.unwrap())
+			.collect();
+
+		assert_eq!(battery[..3], [10, 20, 25]);
+		assert!(battery.iter().all(|&soc| soc <= 25));
+	}
+}
@@ -0,0 +1,177 @@
+//! Content-addressed trade identifiers shared with `pallet_mediator`.
+//!
+//! The id of a trade is the BLAKE2b-256 hash of the SCALE encoding of the tuple
+//! `(round: u64, occurrence: u32, buyer: Vec<u8>, seller: Vec<u8>, amount: u16, price: u64)`. The
+//! occurrence is the number of identical trades formed before it in the round, so that identical
+//! trades get different ids while the ids do not depend on the order trades are formed in.
+//! Participants are the bytes of their account ids and the price is given in thousandths of a
+//! unit. The pallet derives ids of its trades the same way, so a trade formed by both sides can be
+//! looked up from either side.
+
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use blake2::{digest::consts::U32, Blake2b, Digest};
+use parity_scale_codec::Encode;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::energy_trade_matching::Trade;
+
+/// Factor between prices and the thousandths they are identified by
+pub const PRICE_SCALE: f64 = 1000.0;
+
+/// Identifier of a trade within the round it was formed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TradeId(pub [u8; 32]);
+
+impl TradeId {
+	/// Derive the id from the parts of a trade as the pallet stores them.
+	pub fn derive(
+		round: u64,
+		occurrence: u32,
+		buyer: &[u8],
+		seller: &[u8],
+		amount: u16,
+		price: u64,
+	) -> Self {
+		let preimage = (round, occurrence, buyer, seller, amount, price).encode();
+		TradeId(Blake2b::<U32>::digest(preimage).into())
+	}
+
+	/// Derive the id of the trade formed in the given round after as many identical trades as
+	/// given by the occurrence.
+	pub fn of(round: u64, occurrence: u32, trade: &Trade) -> Self {
+		TradeId::derive(
+			round,
+			occurrence,
+			&participant_bytes(&trade.buyer),
+			&participant_bytes(&trade.seller),
+			trade.amount,
+			price_in_thousandths(trade.price),
+		)
+	}
+
+	/// Derive the ids of all trades formed in the given round, counting the occurrences of
+	/// identical trades in the order they are given in.
+	pub fn of_round(round: u64, trades: &[Trade]) -> Vec<Self> {
+		let mut ids = HashSet::with_capacity(trades.len());
+		trades
+			.iter()
+			.map(|trade| {
+				let id = (0..=u32::MAX)
+					.map(|occurrence| TradeId::of(round, occurrence, trade))
+					.find(|id| !ids.contains(id))
+					.expect("a round has fewer than u32::MAX trades");
+				ids.insert(id);
+				id
+			})
+			.collect()
+	}
+}
+
+impl fmt::Display for TradeId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "0x{}", hex::encode(self.0))
+	}
+}
+
+impl FromStr for TradeId {
+	type Err = hex::FromHexError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut id = [0; 32];
+		hex::decode_to_slice(s.strip_prefix("0x").unwrap_or(s), &mut id)?;
+		Ok(TradeId(id))
+	}
+}
+
+impl Serialize for TradeId {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de> Deserialize<'de> for TradeId {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+	}
+}
+
+/// Bytes of a participant's account id.
+///
+/// Ids given in `0x` prefixed hex, like SCALE encoded account ids, are decoded; any other id is
+/// taken as is.
+pub fn participant_bytes(id: &str) -> Vec<u8> {
+	id.strip_prefix("0x")
+		.and_then(|encoded| hex::decode(encoded).ok())
+		.unwrap_or_else(|| id.as_bytes().to_vec())
+}
+
+/// Price rounded to thousandths of a unit.
+pub fn price_in_thousandths(price: f32) -> u64 {
+	(price as f64 * PRICE_SCALE).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Also checked by `pallet_mediator`'s tests
+	const TEST_VECTOR: &str = "0xd20f64dddd0eb9291934d60910feac6377047555a6749fab4c3dd4c7478e3abe";
+
+	#[test]
+	fn ids_match_the_pallet_test_vector() {
+		// Accounts 1 and 2 of the pallet's mock runtime, trading 10 units at 3 as the first trade
+		// of round 7
+		let trade = Trade {
+			amount: 10,
+			price: 3.0,
+			buyer: "0x0200000000000000".into(),
+			seller: "0x0100000000000000".into(),
+			..Default::default()
+		};
+
+		let id = TradeId::of(7, 0, &trade);
+
+		assert_eq!(id, TradeId::derive(7, 0, &2u64.encode(), &1u64.encode(), 10, 3_000));
+		assert_eq!(id.to_string(), TEST_VECTOR);
+		assert_eq!(TEST_VECTOR.parse::<TradeId>().unwrap(), id);
+	}
+
+	#[test]
+	fn ids_differ_by_round_occurrence_and_price() {
+		let trade = Trade {
+			amount: 10,
+			price: 2.35,
+			buyer: "buyer_1".into(),
+			seller: "seller_1".into(),
+			..Default::default()
+		};
+		let cheaper = Trade { price: 2.3, ..trade.clone() };
+
+		assert_eq!(TradeId::of(1, 0, &trade), TradeId::of(1, 0, &trade.clone()));
+		assert_ne!(TradeId::of(1, 0, &trade), TradeId::of(2, 0, &trade));
+		assert_ne!(TradeId::of(1, 0, &trade), TradeId::of(1, 1, &trade));
+		assert_ne!(TradeId::of(1, 0, &trade), TradeId::of(1, 0, &cheaper));
+	}
+
+	#[test]
+	fn ids_of_a_round_do_not_depend_on_the_order_of_its_trades() {
+		let trade = Trade {
+			amount: 10,
+			price: 2.0,
+			buyer: "buyer_1".into(),
+			seller: "seller_1".into(),
+			..Default::default()
+		};
+		let other = Trade { buyer: "buyer_2".into(), ..trade.clone() };
+
+		let ids = TradeId::of_round(1, &[trade.clone(), other.clone(), trade.clone()]);
+		let reordered = TradeId::of_round(1, &[other.clone(), trade.clone(), trade.clone()]);
+
+		assert_eq!(
+			ids,
+			[TradeId::of(1, 0, &trade), TradeId::of(1, 0, &other), TradeId::of(1, 1, &trade)]
+		);
+		assert_eq!(ids.iter().collect::<HashSet<_>>(), reordered.iter().collect::<HashSet<_>>());
+	}
+}
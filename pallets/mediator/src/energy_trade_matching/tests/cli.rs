@@ -0,0 +1,100 @@
+//! Runs the `energy-match` binary on order books in each input format.
+
+use std::{
+	fs,
+	path::PathBuf,
+	process::{Command, Output},
+};
+
+fn fixture(path: &str) -> PathBuf {
+	PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(path)
+}
+
+/// Write the files to a directory of the test's own.
+fn inputs(test: &str, files: &[(&str, &str)]) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("energy-match-{}-{}", test, std::process::id()));
+	fs::create_dir_all(&dir).unwrap();
+	for (name, contents) in files {
+		fs::write(dir.join(name), contents).unwrap();
+	}
+	dir
+}
+
+fn energy_match(args: &[&str]) -> Output {
+	Command::new(env!("CARGO_BIN_EXE_energy-match")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+	assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+	String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn json_and_json_lines_inputs_are_cleared() {
+	let dir = inputs("json", &[("grid.json", r#"{"request_price": 1.5, "offer_price": 2.6}"#)]);
+
+	let output = energy_match(&[
+		"--requests",
+		fixture("round_1/requests.json").to_str().unwrap(),
+		"--offers",
+		fixture("round_1/offers.jsonl").to_str().unwrap(),
+		"--grid",
+		dir.join("grid.json").to_str().unwrap(),
+	]);
+
+	let trades: Vec<serde_json::Value> = serde_json::from_str(&stdout(&output)).unwrap();
+	assert!(!trades.is_empty());
+	assert!(trades.iter().all(|t| t["id"].is_string() && t["amount"].as_u64() > Some(0)));
+}
+
+#[test]
+fn csv_inputs_may_leave_optional_columns_empty() {
+	let dir = inputs(
+		"csv",
+		&[
+			(
+				"requests.csv",
+				"amount,price,buyer,microgrid,preferred_sources,premium,excluded_sources,budget\n\
+				 10,3.0,buyer_1,,,,,\n\
+				 5,3.0,buyer_2,,Pv,0.5,Chp,\n",
+			),
+			(
+				"offers.csv",
+				"amount,price,seller,microgrid,source,carbon_intensity\n\
+				 10,2.0,seller_1,,,\n\
+				 5,2.5,seller_2,,Pv,20\n",
+			),
+			("grid.csv", "request_price,offer_price,carbon_intensity\n1.0,5.0,\n"),
+		],
+	);
+	let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+
+	let output = energy_match(&[
+		"--requests",
+		&path("requests.csv"),
+		"--offers",
+		&path("offers.csv"),
+		"--grid",
+		&path("grid.csv"),
+		"--output-format",
+		"csv",
+	]);
+
+	let trades = stdout(&output);
+	assert!(trades.starts_with("id,amount,price,buyer,seller"));
+	for participant in ["buyer_1", "buyer_2", "seller_1", "seller_2"] {
+		assert!(trades.contains(participant), "{} did not trade", participant);
+	}
+}
+
+#[test]
+fn only_one_input_is_read_from_stdin() {
+	let dir = inputs("stdin", &[("grid.json", r#"{"request_price": 1.5, "offer_price": 2.6}"#)]);
+	let grid = dir.join("grid.json");
+
+	let output =
+		energy_match(&["--requests", "-", "--offers", "-", "--grid", grid.to_str().unwrap()]);
+
+	assert!(!output.status.success());
+	assert!(String::from_utf8_lossy(&output.stderr).contains("stdin"));
+}
@@ -0,0 +1,146 @@
+//! Properties of `generate_trades` over arbitrary orders, including amounts of zero and
+//! `u16::MAX` and prices that are zero, extreme or not finite.
+//!
+//! Every case that completes also shows that the matching terminated. Failures are minimised by
+//! proptest before they are reported.
+
+use energy_trade_matching::{
+	energy_trade_matching::{
+		clear_peer_to_peer_in_mode, generate_trades_in_mode, EnergyOffer, EnergyRequest,
+		EnergySource, MatchingMode, SourcePreference,
+	},
+	invariants,
+};
+use proptest::{prelude::*, sample::select};
+
+fn amount() -> impl Strategy<Value = u16> {
+	prop_oneof![Just(0), Just(u16::MAX), 1u16..100, any::<u16>()]
+}
+
+fn price() -> impl Strategy<Value = f32> {
+	prop_oneof![
+		4 => 0.0f32..10.0,
+		1 => Just(0.0),
+		1 => Just(f32::MAX),
+		1 => Just(f32::MIN),
+		1 => Just(f32::INFINITY),
+		1 => Just(f32::NEG_INFINITY),
+		1 => Just(f32::NAN),
+		2 => any::<f32>(),
+	]
+}
+
+fn budget() -> impl Strategy<Value = Option<f64>> {
+	prop_oneof![
+		2 => Just(None),
+		2 => (0.0f64..500.0).prop_map(Some),
+		1 => Just(Some(0.0)),
+		1 => any::<f64>().prop_map(Some),
+	]
+}
+
+fn source() -> impl Strategy<Value = EnergySource> {
+	select(EnergySource::ALL.to_vec())
+}
+
+fn mode() -> impl Strategy<Value = MatchingMode> {
+	prop_oneof![Just(MatchingMode::Price), Just(MatchingMode::LowCarbon)]
+}
+
+fn requests() -> impl Strategy<Value = Vec<EnergyRequest>> {
+	let origin =
+		(prop::collection::vec(source(), 0..2), price(), prop::collection::vec(source(), 0..2))
+			.prop_map(|(preferred, premium, excluded)| SourcePreference {
+				preferred,
+				premium,
+				excluded,
+			});
+	prop::collection::vec((amount(), price(), origin, budget()), 0..30).prop_map(|requests| {
+		requests
+			.into_iter()
+			.enumerate()
+			.map(|(i, (amount, price, origin, budget))| EnergyRequest {
+				amount,
+				price,
+				buyer: format!("buyer_{}", i),
+				origin,
+				budget,
+			})
+			.collect()
+	})
+}
+
+fn offers() -> impl Strategy<Value = Vec<EnergyOffer>> {
+	prop::collection::vec((amount(), price(), source(), price()), 0..30).prop_map(|offers| {
+		offers
+			.into_iter()
+			.enumerate()
+			.map(|(i, (amount, price, source, carbon_intensity))| EnergyOffer {
+				amount,
+				price,
+				seller: format!("seller_{}", i),
+				source,
+				carbon_intensity,
+			})
+			.collect()
+	})
+}
+
+fn grid() -> impl Strategy<Value = (EnergyRequest, EnergyOffer)> {
+	(price(), price(), price()).prop_map(|(buying_price, selling_price, carbon_intensity)| {
+		(
+			EnergyRequest { price: buying_price, buyer: "grid".into(), ..Default::default() },
+			EnergyOffer {
+				price: selling_price,
+				seller: "grid".into(),
+				carbon_intensity,
+				..Default::default()
+			},
+		)
+	})
+}
+
+proptest! {
+	#[test]
+	fn cleared_rounds_satisfy_the_invariants(
+		requests in requests(),
+		offers in offers(),
+		(grid_request, grid_offer) in grid(),
+		mode in mode(),
+	) {
+		let trades = generate_trades_in_mode(
+			&mut requests.clone(),
+			&mut offers.clone(),
+			&grid_request,
+			&grid_offer,
+			mode,
+		);
+
+		if let Err(violation) =
+			invariants::check(&requests, &offers, &grid_request, &grid_offer, &trades)
+		{
+			return Err(TestCaseError::fail(violation))
+		}
+	}
+
+	#[test]
+	fn peer_to_peer_clearing_conserves_energy(
+		mut requests in requests(),
+		mut offers in offers(),
+		mode in mode(),
+	) {
+		let requested: u64 = requests.iter().map(|r| r.amount as u64).sum();
+		let offered: u64 = offers.iter().map(|o| o.amount as u64).sum();
+
+		let clearing = clear_peer_to_peer_in_mode(&mut requests, &mut offers, mode);
+
+		let traded: u64 = clearing.trades.iter().map(|t| t.amount as u64).sum();
+		let unmatched_requests: u64 =
+			clearing.unmatched_requests.iter().map(|r| r.amount as u64).sum();
+		let unmatched_offers: u64 = clearing.unmatched_offers.iter().map(|o| o.amount as u64).sum();
+		prop_assert_eq!(traded + unmatched_requests, requested);
+		prop_assert_eq!(traded + unmatched_offers, offered);
+		prop_assert!(clearing.unmatched_requests.iter().all(|r| r.amount > 0));
+		prop_assert!(clearing.unmatched_offers.iter().all(|o| o.amount > 0));
+	}
+}
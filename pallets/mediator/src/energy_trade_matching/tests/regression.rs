@@ -0,0 +1,49 @@
+//! Replays recorded rounds and compares the resulting trades with stored golden trade lists.
+//!
+//! After an intended change to the algorithm, re-record the golden lists with
+//! `UPDATE_GOLDEN=1 cargo test --test regression` and review the diff of the fixtures.
+
+use std::path::PathBuf;
+
+use energy_trade_matching::{
+	energy_trade_matching::{EnergyOffer, EnergyRequest},
+	replay::{diff_trades, load_dump, save_dump, RecordedRound},
+};
+
+fn fixture(path: &str) -> PathBuf {
+	PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(path)
+}
+
+fn assert_matches_golden(round: &str) {
+	let recording = RecordedRound::load(
+		fixture(&format!("{}/requests.json", round)),
+		fixture(&format!("{}/offers.jsonl", round)),
+		EnergyRequest {
+			amount: 20,
+			price: 1.5,
+			buyer: String::from("Grid_Buyer"),
+			..Default::default()
+		},
+		EnergyOffer {
+			amount: 20,
+			price: 2.6,
+			seller: String::from("Grid_Seller"),
+			..Default::default()
+		},
+	)
+	.unwrap();
+	let golden = fixture(&format!("{}/golden_trades.json", round));
+
+	let trades = recording.replay();
+	if std::env::var_os("UPDATE_GOLDEN").is_some() {
+		save_dump(&golden, &trades).unwrap();
+	}
+
+	let diff = diff_trades(&load_dump(&golden).unwrap(), &trades);
+	assert!(diff.is_empty(), "Trades of {} differ from the golden list: {}", round, diff);
+}
+
+#[test]
+fn evening_prosumer_round_matches_golden_trades() {
+	assert_matches_golden("round_1");
+}
@@ -14,29 +14,326 @@ mod tests;
 #[cfg(feature = "runtime-benchmarks")]
 mod benchmarking;
 
+pub mod weights;
+pub use weights::WeightInfo;
+
 #[frame_support::pallet]
 pub mod pallet {
-    use frame_support::{dispatch::DispatchResult, pallet_prelude::*, traits::Currency};
+    use frame_support::{
+        dispatch::DispatchResult,
+        pallet_prelude::*,
+        sp_io::hashing::blake2_256,
+        sp_runtime::traits::{Hash, SaturatedConversion, Saturating, Zero},
+        sp_std::vec::Vec,
+        traits::{BalanceStatus, Currency, ExistenceRequirement, ReservableCurrency},
+        weights::Weight,
+    };
     use frame_system::pallet_prelude::*;
+    #[cfg(feature = "std")]
+    use serde::{Deserialize, Serialize};
     pub use crate::pallet;
+    use crate::WeightInfo;
+
+    /// Content-addressed id of a trade, derived the same way as `energy_trade_matching::trade_id`
+    pub type TradeId = [u8; 32];
+
+    /// Factor between whole unit prices of trades and the thousandths their ids are derived from
+    pub const PRICE_SCALE: u64 = 1000;
+
+    /// Index of the trading period orders are placed for and trades are formed in
+    pub type PeriodIndex = u64;
+
+    /// Id of an order, unique among all orders ever placed
+    pub type OrderId = u64;
+
+    /// Id of a smart meter measuring the energy a participant imports and exports
+    pub type MeterId = u64;
+
+    /// Id of a microgrid participants can be connected through
+    pub type MicrogridId = u32;
+
+    pub type BalanceOf<T> =
+        <<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
 
     /// Configure the pallet by specifying the parameters and types on which it depends.
     #[pallet::config]
     pub trait Config: frame_system::Config {
         /// Because this pallet emits events, it depends on the runtime's definition of an event.
         type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
-        type Currency: Currency<Self::AccountId>;
+        /// Holds the price of requests in reserve until their trades are settled
+        type Currency: ReservableCurrency<Self::AccountId>;
+
+        /// Most requests, and most offers, a single period can hold
+        #[pallet::constant]
+        type MaxOrdersPerPeriod: Get<u32>;
+
+        /// Most requests, and most offers, a single account can hold in a period
+        #[pallet::constant]
+        type MaxOrdersPerAccount: Get<u32>;
+
+        /// Most periods after the current one orders can be placed for
+        #[pallet::constant]
+        type MaxPeriodsAhead: Get<PeriodIndex>;
+
+        /// Amount reserved for every order, request or offer, until it is cancelled or cleared
+        #[pallet::constant]
+        type OrderDeposit: Get<BalanceOf<Self>>;
+
+        /// Most trades a single period can form, at least four times `MaxOrdersPerPeriod`, since
+        /// each request and offer forms up to one trade with a peer and one with the grid
+        #[pallet::constant]
+        type MaxTradesPerPeriod: Get<u32>;
+
+        /// Most participants that can be registered at once
+        #[pallet::constant]
+        type MaxParticipants: Get<u32>;
+
+        /// Least amount of energy an order can be for
+        #[pallet::constant]
+        type MinOrderAmount: Get<u16>;
+
+        /// Most amount of energy an order can be for
+        #[pallet::constant]
+        type MaxOrderAmount: Get<u16>;
+
+        /// Lowest price per unit an order can be placed at
+        #[pallet::constant]
+        type MinPrice: Get<u8>;
+
+        /// Highest price per unit an order can be placed at
+        #[pallet::constant]
+        type MaxPrice: Get<u8>;
+
+        /// Blocks in a period of delivery, period `n` starting at block `n * PeriodLength`
+        #[pallet::constant]
+        type PeriodLength: Get<Self::BlockNumber>;
+
+        /// Blocks before the start of a period at which its gate closes and it is cleared
+        #[pallet::constant]
+        type GateClosureOffset: Get<Self::BlockNumber>;
+
+        /// Most orders cleared in a single block, larger books being cleared over several blocks
+        #[pallet::constant]
+        type MaxOrdersPerBlock: Get<u32>;
+
+        /// Account of the grid operator, the counterparty of trades with the grid. It pays for the
+        /// energy the grid buys from its free balance, so it has to be funded, at genesis or by
+        /// transfers, for peers to be paid for what they sell to the grid.
+        #[pallet::constant]
+        type GridOperator: Get<Self::AccountId>;
+
+        /// Watt-hours in a unit of energy traded
+        #[pallet::constant]
+        type WattHoursPerUnit: Get<u32>;
+
+        /// Amount reserved from a participant for as long as they are registered
+        #[pallet::constant]
+        type RegistrationDeposit: Get<BalanceOf<Self>>;
+
+        /// Cleared periods whose trades, certificates, failed settlements, traded energy, meter
+        /// readings and deliveries are kept, at least two so that meters can report on a period
+        /// once it is delivered. Earlier periods are pruned.
+        #[pallet::constant]
+        type RetainedPeriods: Get<u32>;
+
+        /// Most entries of a period pruned in a single block, larger periods being pruned over
+        /// several blocks
+        #[pallet::constant]
+        type MaxPrunedPerBlock: Get<u32>;
+
+        /// Origin allowed to publish the tariff of the grid and, besides root, to grant privileged
+        /// roles
+        type GridOrigin: EnsureOrigin<Self::RuntimeOrigin>;
+
+        /// Weight information for extrinsics and clearing in this pallet
+        type WeightInfo: WeightInfo;
     }
 
     #[pallet::pallet]
     #[pallet::generate_store(pub (super) trait Store)]
-    #[pallet::without_storage_info]
     pub struct Pallet<T>(_);
 
-    /// Storage Map for Trades by Hash to a Trade
+    /// Storage Map for Trades by TradeId to a Trade
     #[pallet::storage]
     #[pallet::getter(fn trades)]
-    pub(super) type Trades<T: Config> = StorageMap<_, Twox64Concat, T::Hash, Trade<T>>;
+    pub(super) type Trades<T: Config> = StorageMap<_, Twox64Concat, TradeId, Trade<T>>;
+
+    /// Storage Map for certificates of origin by TradeId of the Trade they belong to
+    #[pallet::storage]
+    #[pallet::getter(fn certificates)]
+    pub(super) type Certificates<T: Config> = StorageMap<_, Twox64Concat, TradeId, Certificate>;
+
+    /// Storage Map for the ids of the trades formed in each period
+    #[pallet::storage]
+    #[pallet::getter(fn period_trades)]
+    pub(super) type PeriodTrades<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        PeriodIndex,
+        BoundedVec<TradeId, T::MaxTradesPerPeriod>,
+        ValueQuery,
+    >;
+
+    /// The earliest period not cleared yet
+    #[pallet::storage]
+    #[pallet::getter(fn current_period)]
+    pub(super) type CurrentPeriod<T: Config> = StorageValue<_, PeriodIndex, ValueQuery>;
+
+    /// The earliest period whose trades and deliveries are not pruned yet
+    #[pallet::storage]
+    #[pallet::getter(fn pruned_period)]
+    pub(super) type PrunedPeriod<T: Config> = StorageValue<_, PeriodIndex, ValueQuery>;
+
+    /// How far clearing the current period got, while it is cleared over several blocks
+    #[pallet::storage]
+    #[pallet::getter(fn clearing)]
+    pub(super) type Clearing<T: Config> = StorageValue<_, ClearingCursor>;
+
+    /// Storage Map for what each participant bought and sold in the period being cleared, until
+    /// it is settled
+    #[pallet::storage]
+    pub(super) type PeriodStatements<T: Config> =
+        StorageMap<_, Twox64Concat, T::AccountId, Statement<BalanceOf<T>>, ValueQuery>;
+
+    /// Storage Map for what participants could not pay of what they owed in a period, for
+    /// operators to follow up
+    #[pallet::storage]
+    #[pallet::getter(fn failed_settlements)]
+    pub(super) type FailedSettlements<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        PeriodIndex,
+        Twox64Concat,
+        T::AccountId,
+        FailedSettlement<BalanceOf<T>>,
+    >;
+
+    /// The id the next order placed is given
+    #[pallet::storage]
+    #[pallet::getter(fn next_order_id)]
+    pub(super) type NextOrderId<T: Config> = StorageValue<_, OrderId, ValueQuery>;
+
+    /// Storage Map for the period and side of the book of each open order by its id
+    #[pallet::storage]
+    #[pallet::getter(fn order_location)]
+    pub(super) type OrderLocations<T: Config> =
+        StorageMap<_, Twox64Concat, OrderId, (PeriodIndex, OrderSide)>;
+
+    /// Storage Map for the requests to buy energy by the period they are placed for
+    #[pallet::storage]
+    #[pallet::getter(fn requests)]
+    pub(super) type Requests<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        PeriodIndex,
+        BoundedVec<Order<T>, T::MaxOrdersPerPeriod>,
+        ValueQuery,
+    >;
+
+    /// Storage Map for the offers to sell energy by the period they are placed for, holding the
+    /// amounts left of them while the period is cleared
+    #[pallet::storage]
+    #[pallet::getter(fn offers)]
+    pub(super) type Offers<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        PeriodIndex,
+        BoundedVec<Order<T>, T::MaxOrdersPerPeriod>,
+        ValueQuery,
+    >;
+
+    /// Storage Map for the energy each participant bought in a period less what they sold, in
+    /// units, as their trades are recorded
+    #[pallet::storage]
+    #[pallet::getter(fn traded_energy)]
+    pub(super) type TradedEnergy<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        PeriodIndex,
+        Twox64Concat,
+        T::AccountId,
+        i64,
+        ValueQuery,
+    >;
+
+    /// Storage Map for the key and participant of each registered meter by its id
+    #[pallet::storage]
+    #[pallet::getter(fn meters)]
+    pub(super) type Meters<T: Config> =
+        StorageMap<_, Twox64Concat, MeterId, Meter<T::AccountId>>;
+
+    /// Storage Map for the readings of each meter by the period they were taken over
+    #[pallet::storage]
+    #[pallet::getter(fn meter_readings)]
+    pub(super) type MeterReadings<T: Config> =
+        StorageDoubleMap<_, Twox64Concat, PeriodIndex, Twox64Concat, MeterId, MeterReading>;
+
+    /// Storage Map for the energy each participant traded and was metered with in a period, as
+    /// the readings of their meters come in
+    #[pallet::storage]
+    #[pallet::getter(fn deliveries)]
+    pub(super) type Deliveries<T: Config> = StorageDoubleMap<
+        _,
+        Twox64Concat,
+        PeriodIndex,
+        Twox64Concat,
+        T::AccountId,
+        Delivery,
+    >;
+
+    /// The tariff of the grid, applying to every period with no tariff of its own scheduled.
+    /// Until a tariff is published it has no capacity, so nothing trades with the grid.
+    #[pallet::storage]
+    #[pallet::getter(fn grid_tariff)]
+    pub(super) type CurrentGridTariff<T: Config> = StorageValue<_, GridTariff, ValueQuery>;
+
+    /// Storage Map for the tariffs of the grid by the period they are scheduled for, each
+    /// applying to its period alone in place of the current tariff
+    #[pallet::storage]
+    #[pallet::getter(fn scheduled_grid_tariffs)]
+    pub(super) type ScheduledGridTariffs<T: Config> =
+        StorageMap<_, Twox64Concat, PeriodIndex, GridTariff>;
+
+    /// Storage Map for the registered participants of the market by their account
+    #[pallet::storage]
+    #[pallet::getter(fn participants)]
+    pub(super) type Participants<T: Config> =
+        CountedStorageMap<_, Twox64Concat, T::AccountId, Participant<BalanceOf<T>>>;
+
+    #[pallet::genesis_config]
+    pub struct GenesisConfig<T: Config> {
+        /// Participants registered without a deposit, as `(account, role, microgrid,
+        /// metering_point)`
+        pub participants: Vec<(T::AccountId, Role, Option<MicrogridId>, MeterId)>,
+    }
+
+    #[cfg(feature = "std")]
+    impl<T: Config> Default for GenesisConfig<T> {
+        fn default() -> Self {
+            Self { participants: Vec::new() }
+        }
+    }
+
+    #[pallet::genesis_build]
+    impl<T: Config> GenesisBuild<T> for GenesisConfig<T> {
+        fn build(&self) {
+            assert!(
+                self.participants.len() as u32 <= T::MaxParticipants::get(),
+                "more genesis participants than MaxParticipants"
+            );
+            for (who, role, microgrid, metering_point) in &self.participants {
+                let participant = Participant {
+                    role: *role,
+                    microgrid: *microgrid,
+                    metering_point: *metering_point,
+                    active: true,
+                    deposit: Zero::zero(),
+                    open_orders: 0,
+                };
+                Participants::<T>::insert(who, participant);
+            }
+        }
+    }
 
     // Pallets use events to inform users when important changes are made.
     // https://docs.substrate.io/v3/runtime/events-and-errors
@@ -45,35 +342,1048 @@ pub mod pallet {
     pub enum Event<T: Config> {
         /// Event documentation should end with an array that provides descriptive names for event
         /// parameters. [something, who]
-        TradeAdded(u16, u8, T::AccountId, T::AccountId, T::Hash),
+        TradeAdded(u16, u8, T::AccountId, T::AccountId, TradeId),
+        /// All trades of an account in a period were settled at once, by paying or receiving the
+        /// difference between what it bought and sold. [who, paid, received]
+        TradesSettled(T::AccountId, BalanceOf<T>, BalanceOf<T>),
+        /// A request to buy energy was placed. [who, order, period, amount, price]
+        RequestSubmitted(T::AccountId, OrderId, PeriodIndex, u16, u8),
+        /// An offer to sell energy was placed. [who, order, period, amount, price]
+        OfferSubmitted(T::AccountId, OrderId, PeriodIndex, u16, u8),
+        /// An open order was withdrawn by its owner. [who, order]
+        OrderCancelled(T::AccountId, OrderId),
+        /// The amount and price of an open order were changed by its owner.
+        /// [who, order, amount, price]
+        OrderAmended(T::AccountId, OrderId, u16, u8),
+        /// All orders of a period were cleared. [period]
+        PeriodCleared(PeriodIndex),
+        /// Clearing left part of an order neither matched with peers nor traded with the grid.
+        /// [order, amount]
+        OrderUnmatched(OrderId, u16),
+        /// A participant could not pay in full what they owed for a period.
+        /// [who, period, reason, unpaid]
+        SettlementFailed(T::AccountId, PeriodIndex, SettlementFailure, BalanceOf<T>),
+        /// A meter was registered for a participant. [meter, key, participant]
+        MeterRegistered(MeterId, T::AccountId, T::AccountId),
+        /// A meter reported its reading over a period. [meter, period, imported_wh, exported_wh]
+        MeterReadingSubmitted(MeterId, PeriodIndex, u64, u64),
+        /// The metered energy of a participant was compared with their trades.
+        /// [who, period, deviation_wh]
+        DeliveryReconciled(T::AccountId, PeriodIndex, i64),
+        /// An account registered as a participant of the market. [who, role]
+        ParticipantRegistered(T::AccountId, Role),
+        /// A participant left the market and got their deposit back. [who]
+        ParticipantDeregistered(T::AccountId),
+        /// A participant was allowed or barred from placing orders. [who, active]
+        ParticipantStatusChanged(T::AccountId, bool),
+        /// A participant was granted a role. [who, role]
+        ParticipantRoleChanged(T::AccountId, Role),
+        /// The tariff of the grid was published, for the period alone if given or else for every
+        /// period cleared from the next on with no tariff of its own. [period, tariff]
+        GridTariffSet(Option<PeriodIndex>, GridTariff),
     }
 
     // Errors inform users that something went wrong.
     #[pallet::error]
     pub enum Error<T> {
-        /// Error names should be descriptive.
-        NoneValue,
-        /// Errors should have helpful documentation associated with them.
-        StorageOverflow,
+        /// The amount of the order is below `MinOrderAmount` or above `MaxOrderAmount`.
+        AmountOutOfBounds,
+        /// The price of the order is below `MinPrice` or above `MaxPrice`.
+        PriceOutOfBounds,
+        /// The period already holds `MaxOrdersPerPeriod` orders of this kind.
+        TooManyOrders,
+        /// The gate of the period has closed, so its orders can no longer change.
+        GateClosed,
+        /// The gate of the period has not closed yet, so it can not be cleared.
+        GateOpen,
+        /// There is no open order with the id.
+        UnknownOrder,
+        /// The order belongs to another account.
+        NotOwner,
+        /// The free balance of the account does not cover the order deposit, along with the
+        /// amount times the price of a request.
+        InsufficientFunds,
+        /// There is no meter registered with the id.
+        UnknownMeter,
+        /// The reading is not signed with the key of the meter.
+        NotMeterKey,
+        /// The delivery period has not ended yet, so it can not be read.
+        PeriodNotDelivered,
+        /// The meter already reported its reading over the period.
+        ReadingExists,
+        /// The account is already registered as a participant.
+        AlreadyRegistered,
+        /// The account is not registered as a participant.
+        NotRegistered,
+        /// The participant is not allowed to place orders.
+        InactiveParticipant,
+        /// The participant has orders that are neither cancelled nor cleared yet.
+        HasOpenOrders,
+        /// The period already holds `MaxTradesPerPeriod` trades.
+        TooManyTrades,
+        /// The period is no longer retained, so it takes no more readings.
+        PeriodPruned,
+        /// `MaxParticipants` participants are registered already.
+        TooManyParticipants,
+        /// The account already holds `MaxOrdersPerAccount` orders of this kind in the period.
+        TooManyAccountOrders,
+        /// The period is more than `MaxPeriodsAhead` periods after the current one.
+        PeriodTooFarAhead,
+        /// Only requests can prefer or exclude sources.
+        PreferenceOfOffer,
+        /// Only `GridOrigin` or root can grant the role.
+        PrivilegedRole,
+        /// The role of the participant does not place orders of this side.
+        SideNotAllowed,
     }
 
     #[pallet::hooks]
-    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {}
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+        /// Clear the current period once its gate has closed, up to `MaxOrdersPerBlock` orders
+        /// per block.
+        fn on_initialize(_now: BlockNumberFor<T>) -> Weight {
+            let weight = Self::prune(T::MaxPrunedPerBlock::get());
+            let period = CurrentPeriod::<T>::get();
+            if !Clearing::<T>::exists() && !Self::is_gate_closed(period) {
+                return weight.saturating_add(T::DbWeight::get().reads(2))
+            }
+
+            weight.saturating_add(Self::clear(period, T::MaxOrdersPerBlock::get()))
+        }
+
+        fn integrity_test() {
+            assert!(
+                T::RetainedPeriods::get() >= 2,
+                "RetainedPeriods prunes periods before their meters can report on them"
+            );
+            assert!(
+                T::MaxTradesPerPeriod::get() / 4 >= T::MaxOrdersPerPeriod::get(),
+                "MaxTradesPerPeriod does not hold the trades MaxOrdersPerPeriod orders can form"
+            );
+
+            let block_weights = T::BlockWeights::get();
+            let normal = block_weights.get(DispatchClass::Normal);
+            assert!(
+                Self::max_clearing_weight()
+                    .all_lte(normal.max_extrinsic.unwrap_or(block_weights.max_block)),
+                "organize_trades can not clear MaxOrdersPerPeriod orders within a block"
+            );
+            // A block clearing a period still has room for a full load of normal extrinsics
+            let left_by_normal = block_weights
+                .max_block
+                .saturating_sub(normal.max_total.unwrap_or(block_weights.max_block));
+            assert!(
+                Self::max_block_clearing_weight().all_lte(left_by_normal),
+                "on_initialize can not clear MaxOrdersPerBlock orders and prune MaxPrunedPerBlock \
+                 entries alongside normal extrinsics"
+            );
+        }
+    }
 
     #[pallet::call]
     impl<T: Config> Pallet<T> {
-        #[pallet::weight(100000)]
+        /// Finish clearing the current period, whose gate has closed, without waiting for the
+        /// following blocks to do so, and move on to the next.
+        #[pallet::weight(Pallet::<T>::max_clearing_weight())]
         #[pallet::call_index(1)]
-        pub fn organize_trades(origin: OriginFor<T>) -> DispatchResult {
+        pub fn organize_trades(origin: OriginFor<T>) -> DispatchResultWithPostInfo {
+            ensure_signed(origin)?;
+
+            let period = CurrentPeriod::<T>::get();
+            ensure!(Self::is_gate_closed(period), Error::<T>::GateOpen);
+
+            Ok(Some(Self::clear(period, u32::MAX)).into())
+        }
+
+        /// Place a request to buy the amount of energy at up to the price per unit in the period,
+        /// reserving the amount times the price until its trades are settled. The request pays up
+        /// to the premium on top of its price for energy of a preferred source, which is reserved
+        /// as well, and never buys energy of an excluded source from peers.
+        #[pallet::weight(T::WeightInfo::submit_request())]
+        #[pallet::call_index(2)]
+        pub fn submit_request(
+            origin: OriginFor<T>,
+            amount: u16,
+            price: u8,
+            period: PeriodIndex,
+            preference: SourcePreference,
+        ) -> DispatchResult {
             let who = ensure_signed(origin)?;
 
-            // Trade matching logic
+            let side = OrderSide::Request;
+            let source = Default::default();
+            let id = Self::submit(who.clone(), side, amount, price, period, source, preference)?;
+            let event = Event::RequestSubmitted(who.clone(), id, period, amount, price);
+            Self::deposit_order_event(&who, event);
 
             Ok(())
         }
+
+        /// Place an offer to sell the amount of energy, generated from the source, at no less than
+        /// the price per unit in the period. Its trades are certified with the source.
+        #[pallet::weight(T::WeightInfo::submit_offer())]
+        #[pallet::call_index(3)]
+        pub fn submit_offer(
+            origin: OriginFor<T>,
+            amount: u16,
+            price: u8,
+            period: PeriodIndex,
+            source: EnergySource,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+
+            let side = OrderSide::Offer;
+            let preference = Default::default();
+            let id = Self::submit(who.clone(), side, amount, price, period, source, preference)?;
+            let event = Event::OfferSubmitted(who.clone(), id, period, amount, price);
+            Self::deposit_order_event(&who, event);
+
+            Ok(())
+        }
+
+        /// Withdraw an order of the caller's before the gate of its period closes.
+        #[pallet::weight(T::WeightInfo::cancel_order())]
+        #[pallet::call_index(4)]
+        pub fn cancel_order(origin: OriginFor<T>, order_id: OrderId) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+
+            let (side, order) = Self::mutate_open_order(&who, order_id, |book, index| {
+                book.remove(index);
+                Ok(())
+            })?;
+            if side == OrderSide::Request {
+                T::Currency::unreserve(&who, Self::order_value(&order));
+            }
+            OrderLocations::<T>::remove(order_id);
+            Self::close_order(&who);
+            Self::deposit_order_event(&who, Event::OrderCancelled(who.clone(), order_id));
+
+            Ok(())
+        }
+
+        /// Change the amount, price and source preference of an order of the caller's before the
+        /// gate of its period closes. The order keeps its place among orders of equal price and
+        /// the source of its energy, and the reserve of a request is adjusted to its new value.
+        /// Only requests can have a source preference.
+        #[pallet::weight(T::WeightInfo::amend_order())]
+        #[pallet::call_index(5)]
+        pub fn amend_order(
+            origin: OriginFor<T>,
+            order_id: OrderId,
+            amount: u16,
+            price: u8,
+            preference: SourcePreference,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+
+            let source = Default::default();
+            let amended = Self::order(who.clone(), order_id, amount, price, source, preference)?;
+            let (side, order) = Self::mutate_open_order(&who, order_id, |book, index| {
+                let order = book.iter_mut().nth(index).ok_or(Error::<T>::UnknownOrder)?;
+                *order = Order { source: order.source, ..amended.clone() };
+                Ok(())
+            })?;
+            if side == OrderSide::Offer {
+                ensure!(amended.preference == Default::default(), Error::<T>::PreferenceOfOffer);
+            }
+            if side == OrderSide::Request {
+                let (reserved, needed) = (Self::order_value(&order), Self::order_value(&amended));
+                if needed > reserved {
+                    T::Currency::reserve(&who, needed - reserved)
+                        .map_err(|_| Error::<T>::InsufficientFunds)?;
+                } else {
+                    T::Currency::unreserve(&who, reserved - needed);
+                }
+            }
+            let event = Event::OrderAmended(who.clone(), order_id, amount, price);
+            Self::deposit_order_event(&who, event);
+
+            Ok(())
+        }
+
+        /// Register the meter of the registered participant, whose readings are signed with the
+        /// key. Registering the meter again replaces its key and participant.
+        #[pallet::weight(T::WeightInfo::register_meter())]
+        #[pallet::call_index(6)]
+        pub fn register_meter(
+            origin: OriginFor<T>,
+            meter_id: MeterId,
+            key: T::AccountId,
+            participant: T::AccountId,
+        ) -> DispatchResult {
+            ensure_root(origin)?;
+
+            ensure!(Participants::<T>::contains_key(&participant), Error::<T>::NotRegistered);
+            let meter = Meter { key: key.clone(), participant: participant.clone() };
+            Meters::<T>::insert(meter_id, meter);
+            Self::deposit_event(Event::MeterRegistered(meter_id, key, participant));
+
+            Ok(())
+        }
+
+        /// Report the energy the meter imported and exported over the period, once it has been
+        /// delivered, and reconcile the participant of the meter's trades in the period with it.
+        #[pallet::weight(T::WeightInfo::submit_meter_reading())]
+        #[pallet::call_index(7)]
+        pub fn submit_meter_reading(
+            origin: OriginFor<T>,
+            meter_id: MeterId,
+            period: PeriodIndex,
+            imported_wh: u64,
+            exported_wh: u64,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+
+            let meter = Meters::<T>::get(meter_id).ok_or(Error::<T>::UnknownMeter)?;
+            ensure!(meter.key == who, Error::<T>::NotMeterKey);
+            ensure!(
+                frame_system::Pallet::<T>::block_number() >=
+                    Self::period_start(period.saturating_add(1)),
+                Error::<T>::PeriodNotDelivered
+            );
+            ensure!(
+                period.saturating_add(T::RetainedPeriods::get().into()) >=
+                    CurrentPeriod::<T>::get(),
+                Error::<T>::PeriodPruned
+            );
+            ensure!(
+                !MeterReadings::<T>::contains_key(period, meter_id),
+                Error::<T>::ReadingExists
+            );
+
+            let reading = MeterReading { imported_wh, exported_wh };
+            MeterReadings::<T>::insert(period, meter_id, reading);
+            Self::deposit_event(Event::MeterReadingSubmitted(
+                meter_id,
+                period,
+                imported_wh,
+                exported_wh,
+            ));
+            Self::reconcile(&meter.participant, period, &reading);
+
+            Ok(())
+        }
+
+        /// Register the caller as an active participant of the market in the role, connected
+        /// through the microgrid if any and metered at the metering point, reserving
+        /// `RegistrationDeposit` until they deregister. Privileged roles are granted by
+        /// `set_participant_role` instead.
+        #[pallet::weight(T::WeightInfo::register_participant())]
+        #[pallet::call_index(8)]
+        pub fn register_participant(
+            origin: OriginFor<T>,
+            role: Role,
+            microgrid: Option<MicrogridId>,
+            metering_point: MeterId,
+        ) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+
+            ensure!(!role.is_privileged(), Error::<T>::PrivilegedRole);
+            ensure!(!Participants::<T>::contains_key(&who), Error::<T>::AlreadyRegistered);
+            ensure!(
+                Participants::<T>::count() < T::MaxParticipants::get(),
+                Error::<T>::TooManyParticipants
+            );
+            let deposit = T::RegistrationDeposit::get();
+            T::Currency::reserve(&who, deposit).map_err(|_| Error::<T>::InsufficientFunds)?;
+            let participant = Participant {
+                role,
+                microgrid,
+                metering_point,
+                active: true,
+                deposit,
+                open_orders: 0,
+            };
+            Participants::<T>::insert(&who, participant);
+            Self::deposit_event(Event::ParticipantRegistered(who, role));
+
+            Ok(())
+        }
+
+        /// Remove the caller from the registry of participants and release their deposit, once
+        /// all their orders are cancelled or cleared.
+        #[pallet::weight(T::WeightInfo::deregister_participant())]
+        #[pallet::call_index(9)]
+        pub fn deregister_participant(origin: OriginFor<T>) -> DispatchResult {
+            let who = ensure_signed(origin)?;
+
+            let participant = Participants::<T>::get(&who).ok_or(Error::<T>::NotRegistered)?;
+            ensure!(participant.open_orders == 0, Error::<T>::HasOpenOrders);
+            Participants::<T>::remove(&who);
+            T::Currency::unreserve(&who, participant.deposit);
+            Self::deposit_event(Event::ParticipantDeregistered(who));
+
+            Ok(())
+        }
+
+        /// Allow or bar the participant from placing orders.
+        #[pallet::weight(T::WeightInfo::set_participant_active())]
+        #[pallet::call_index(10)]
+        pub fn set_participant_active(
+            origin: OriginFor<T>,
+            who: T::AccountId,
+            active: bool,
+        ) -> DispatchResult {
+            ensure_root(origin)?;
+
+            Participants::<T>::try_mutate(&who, |participant| {
+                participant.as_mut().ok_or(Error::<T>::NotRegistered)?.active = active;
+                Ok::<_, Error<T>>(())
+            })?;
+            Self::deposit_event(Event::ParticipantStatusChanged(who, active));
+
+            Ok(())
+        }
+
+        /// Publish the prices and capacities of trading with the grid, for the period alone if
+        /// given, as long as its gate is open, or else as the current tariff of every period with
+        /// none of its own. Nothing trades with the grid until a tariff is published.
+        #[pallet::weight(T::WeightInfo::set_grid_tariff())]
+        #[pallet::call_index(11)]
+        pub fn set_grid_tariff(
+            origin: OriginFor<T>,
+            tariff: GridTariff,
+            period: Option<PeriodIndex>,
+        ) -> DispatchResult {
+            T::GridOrigin::ensure_origin(origin)?;
+
+            match period {
+                Some(period) => {
+                    ensure!(!Self::is_gate_closed(period), Error::<T>::GateClosed);
+                    ScheduledGridTariffs::<T>::insert(period, tariff);
+                },
+                None => CurrentGridTariff::<T>::put(tariff),
+            }
+            Self::deposit_event(Event::GridTariffSet(period, tariff));
+
+            Ok(())
+        }
+
+        /// Grant the participant a role, privileged ones included. Orders they placed already
+        /// stay in the book.
+        #[pallet::weight(T::WeightInfo::set_participant_role())]
+        #[pallet::call_index(12)]
+        pub fn set_participant_role(
+            origin: OriginFor<T>,
+            who: T::AccountId,
+            role: Role,
+        ) -> DispatchResult {
+            T::GridOrigin::try_origin(origin).map(|_| ()).or_else(ensure_root)?;
+
+            Participants::<T>::try_mutate(&who, |participant| {
+                participant.as_mut().ok_or(Error::<T>::NotRegistered)?.role = role;
+                Ok::<_, Error<T>>(())
+            })?;
+            Self::deposit_event(Event::ParticipantRoleChanged(who, role));
+
+            Ok(())
+        }
+    }
+
+    impl<T: Config> Pallet<T> {
+        /// Place an order of an active participant whose role places orders of the side in the
+        /// book of the period, if its gate is still open and it is not too far ahead, reserving the
+        /// order deposit and the value of a request, and return its id.
+        fn submit(
+            who: T::AccountId,
+            side: OrderSide,
+            amount: u16,
+            price: u8,
+            period: PeriodIndex,
+            source: EnergySource,
+            preference: SourcePreference,
+        ) -> Result<OrderId, DispatchError> {
+            let participant = Participants::<T>::get(&who).ok_or(Error::<T>::NotRegistered)?;
+            ensure!(participant.active, Error::<T>::InactiveParticipant);
+            ensure!(participant.role.places(side), Error::<T>::SideNotAllowed);
+            ensure!(!Self::is_gate_closed(period), Error::<T>::GateClosed);
+            let last_period = CurrentPeriod::<T>::get().saturating_add(T::MaxPeriodsAhead::get());
+            ensure!(period <= last_period, Error::<T>::PeriodTooFarAhead);
+            let id = NextOrderId::<T>::get();
+            let order = Self::order(who.clone(), id, amount, price, source, preference)?;
+            let mut book = match side {
+                OrderSide::Request => Requests::<T>::get(period),
+                OrderSide::Offer => Offers::<T>::get(period),
+            };
+            let placed = book.iter().filter(|o| o.account == who).count() as u32;
+            ensure!(placed < T::MaxOrdersPerAccount::get(), Error::<T>::TooManyAccountOrders);
+            let mut reserve = T::OrderDeposit::get();
+            if side == OrderSide::Request {
+                reserve = reserve.saturating_add(Self::order_value(&order));
+            }
+            book.try_push(order).map_err(|_| Error::<T>::TooManyOrders)?;
+            T::Currency::reserve(&who, reserve).map_err(|_| Error::<T>::InsufficientFunds)?;
+
+            match side {
+                OrderSide::Request => Requests::<T>::insert(period, book),
+                OrderSide::Offer => Offers::<T>::insert(period, book),
+            }
+
+            NextOrderId::<T>::put(id.saturating_add(1));
+            OrderLocations::<T>::insert(id, (period, side));
+            let open_orders = participant.open_orders.saturating_add(1);
+            Participants::<T>::insert(&who, Participant { open_orders, ..participant });
+
+            Ok(id)
+        }
+
+        /// Count an order of the participant as no longer open, once it is cancelled or cleared,
+        /// and release its deposit.
+        fn close_order(who: &T::AccountId) {
+            T::Currency::unreserve(who, T::OrderDeposit::get());
+            Participants::<T>::mutate(who, |participant| {
+                if let Some(participant) = participant {
+                    participant.open_orders = participant.open_orders.saturating_sub(1);
+                }
+            });
+        }
+
+        /// Change the open order of the account in its book, given along with the index of the
+        /// order in it, as long as the gate of its period is open. Returns the side of the book
+        /// and the order as it was.
+        fn mutate_open_order(
+            who: &T::AccountId,
+            order_id: OrderId,
+            f: impl FnOnce(
+                &mut BoundedVec<Order<T>, T::MaxOrdersPerPeriod>,
+                usize,
+            ) -> Result<(), DispatchError>,
+        ) -> Result<(OrderSide, Order<T>), DispatchError> {
+            let (period, side) =
+                OrderLocations::<T>::get(order_id).ok_or(Error::<T>::UnknownOrder)?;
+            let mutate = |book: &mut BoundedVec<Order<T>, T::MaxOrdersPerPeriod>| {
+                let index =
+                    book.iter().position(|o| o.id == order_id).ok_or(Error::<T>::UnknownOrder)?;
+                ensure!(book[index].account == *who, Error::<T>::NotOwner);
+                ensure!(!Self::is_gate_closed(period), Error::<T>::GateClosed);
+                let order = book[index].clone();
+                f(book, index)?;
+                Ok(order)
+            };
+
+            let order = match side {
+                OrderSide::Request => Requests::<T>::try_mutate(period, mutate),
+                OrderSide::Offer => Offers::<T>::try_mutate(period, mutate),
+            }?;
+            Ok((side, order))
+        }
+
+        /// Deposit an event about an order, with the account of its owner as topic, so that they
+        /// can follow their open orders.
+        fn deposit_order_event(who: &T::AccountId, event: Event<T>) {
+            let event = <T as Config>::RuntimeEvent::from(event).into();
+            frame_system::Pallet::<T>::deposit_event_indexed(&[T::Hashing::hash_of(who)], event);
+        }
+
+        /// An order of the account, if its amount and price are within the configured bounds.
+        fn order(
+            account: T::AccountId,
+            id: OrderId,
+            amount: u16,
+            price: u8,
+            source: EnergySource,
+            preference: SourcePreference,
+        ) -> Result<Order<T>, Error<T>> {
+            ensure!(
+                (T::MinOrderAmount::get()..=T::MaxOrderAmount::get()).contains(&amount),
+                Error::<T>::AmountOutOfBounds
+            );
+            ensure!(
+                (T::MinPrice::get()..=T::MaxPrice::get()).contains(&price),
+                Error::<T>::PriceOutOfBounds
+            );
+
+            Ok(Order { id, account, amount, price, source, preference })
+        }
+
+        /// Amount times price of the order, along with the premium for preferred sources, the
+        /// most a request can cost.
+        pub fn order_value(order: &Order<T>) -> BalanceOf<T> {
+            let limit = order.price as u32 + order.preference.max_premium() as u32;
+            BalanceOf::<T>::from(order.amount as u32).saturating_mul(BalanceOf::<T>::from(limit))
+        }
+
+        /// Weight of clearing a period in a single call. Matching among peers takes up to one step
+        /// per request and per offer, and selling to the grid one more per offer, so at worst
+        /// `3 * MaxOrdersPerPeriod` orders are processed.
+        pub fn max_clearing_weight() -> Weight {
+            let max_orders = T::MaxOrdersPerPeriod::get();
+            T::WeightInfo::clear_orders(max_orders.saturating_mul(3))
+                .saturating_add(T::WeightInfo::finish_clearing(max_orders))
+        }
+
+        /// Weight of clearing a period in a block, at worst processing `MaxOrdersPerBlock` orders,
+        /// finishing the period and pruning `MaxPrunedPerBlock` entries of an earlier one.
+        pub fn max_block_clearing_weight() -> Weight {
+            let max_pruned = T::MaxPrunedPerBlock::get();
+            T::WeightInfo::clear_orders(T::MaxOrdersPerBlock::get())
+                .saturating_add(T::WeightInfo::finish_clearing(T::MaxOrdersPerPeriod::get()))
+                .saturating_add(T::WeightInfo::prune_period(max_pruned, max_pruned))
+        }
+
+        /// First block of delivery of the period.
+        pub fn period_start(period: PeriodIndex) -> T::BlockNumber {
+            T::PeriodLength::get().saturating_mul(period.saturated_into())
+        }
+
+        /// Block at which the gate of the period closes, after which its orders can no longer
+        /// change and it is cleared.
+        pub fn gate_closure(period: PeriodIndex) -> T::BlockNumber {
+            Self::period_start(period).saturating_sub(T::GateClosureOffset::get())
+        }
+
+        /// Whether the gate of the period has closed by the current block.
+        pub fn is_gate_closed(period: PeriodIndex) -> bool {
+            frame_system::Pallet::<T>::block_number() >= Self::gate_closure(period)
+        }
+
+        /// Clear the period by processing up to the given number of orders, carrying on where the
+        /// last call stopped, and return the weight of doing so.
+        ///
+        /// Requests are matched among peers first, and what they are left with is bought from
+        /// the grid, at no more than their price and as far as its tariff allows. Offers left
+        /// over once all requests are served are sold to the grid the same way. The trades are
+        /// recorded as they are formed, and what a request does not spend of its reserve is
+        /// released once it is served. Until the grid publishes a tariff it has no capacity, so
+        /// nothing trades with it. Whatever amount of an order is left unserved or unsold is
+        /// reported. Once all orders are processed they are removed, the trades of the period are
+        /// settled, and the next period becomes the current one.
+        pub fn clear(period: PeriodIndex, max_orders: u32) -> Weight {
+            let mut cursor = match Clearing::<T>::get() {
+                Some(cursor) => cursor,
+                None => Self::start_clearing(period),
+            };
+            let requests = Requests::<T>::get(period);
+            let mut offers = Offers::<T>::get(period).into_inner();
+            let record_and_account = |trade: Trade<T>, certificate| {
+                // A trade there is no room to record is not settled either, though
+                // `integrity_test` makes sure there is room for every trade clearing forms
+                if Self::record_trade(period, trade.clone(), certificate).is_ok() {
+                    Self::add_to_statements(&trade);
+                }
+            };
+
+            let mut tariff = cursor.tariff;
+            let mut processed = Self::match_sorted(
+                &requests,
+                &mut offers,
+                &mut cursor,
+                max_orders,
+                record_and_account,
+                |request, mut spent, mut unserved| {
+                    let amount = unserved.min(tariff.import_capacity);
+                    if amount > 0 && request.price >= tariff.import_price {
+                        tariff.import_capacity -= amount;
+                        unserved -= amount;
+                        spent += amount as u32 * tariff.import_price as u32;
+                        let trade = Trade {
+                            amount,
+                            price: tariff.import_price,
+                            seller: T::GridOperator::get(),
+                            buyer: request.account.clone(),
+                        };
+                        record_and_account(trade, Certificate::default());
+                    }
+                    if unserved > 0 {
+                        Self::deposit_event(Event::OrderUnmatched(request.id, unserved));
+                    }
+                    let unused = Self::order_value(request).saturating_sub(spent.into());
+                    T::Currency::unreserve(&request.account, unused);
+                },
+            );
+            cursor.tariff = tariff;
+
+            let served = cursor.next_request as usize >= requests.len();
+            while served && processed < max_orders && cursor.tariff.export_capacity > 0 {
+                let o = match offers.get(cursor.next_offer as usize) {
+                    Some(o) => o,
+                    None => break,
+                };
+                processed += 1;
+
+                let mut unsold = o.amount;
+                let amount = unsold.min(cursor.tariff.export_capacity);
+                if amount > 0 && o.price <= cursor.tariff.export_price {
+                    cursor.tariff.export_capacity -= amount;
+                    unsold -= amount;
+                    let trade = Trade {
+                        amount,
+                        price: cursor.tariff.export_price,
+                        seller: o.account.clone(),
+                        buyer: T::GridOperator::get(),
+                    };
+                    record_and_account(trade, Certificate { source: o.source, premium: 0 });
+                }
+                if unsold > 0 {
+                    Self::deposit_event(Event::OrderUnmatched(o.id, unsold));
+                }
+                cursor.next_offer += 1;
+            }
+
+            let sold = cursor.next_offer as usize >= offers.len() ||
+                cursor.tariff.export_capacity == 0;
+            let mut weight = T::WeightInfo::clear_orders(processed);
+            if served && sold {
+                // Offers the grid had no capacity left for
+                let unsold = offers.iter().skip(cursor.next_offer as usize);
+                for o in unsold.filter(|o| o.amount > 0) {
+                    Self::deposit_event(Event::OrderUnmatched(o.id, o.amount));
+                }
+                for order in requests.iter().chain(offers.iter()) {
+                    OrderLocations::<T>::remove(order.id);
+                    Self::close_order(&order.account);
+                }
+                Requests::<T>::remove(period);
+                Offers::<T>::remove(period);
+                Clearing::<T>::kill();
+                CurrentPeriod::<T>::put(period.saturating_add(1));
+                Self::settle_period(period);
+                Self::deposit_event(Event::PeriodCleared(period));
+                let book = requests.len().max(offers.len()) as u32;
+                weight = weight.saturating_add(T::WeightInfo::finish_clearing(book));
+            } else {
+                Offers::<T>::insert(period, BoundedVec::truncate_from(offers));
+                Clearing::<T>::put(cursor);
+            }
+
+            weight
+        }
+
+        /// Sort the orders of the period by price, the order they are matched in, and clear it at
+        /// the tariff of the grid scheduled for the period, if any, leaving the current tariff
+        /// for the periods after it.
+        fn start_clearing(period: PeriodIndex) -> ClearingCursor {
+            let tariff = ScheduledGridTariffs::<T>::take(period)
+                .unwrap_or_else(CurrentGridTariff::<T>::get);
+            let mut requests = Requests::<T>::get(period).into_inner();
+            let mut offers = Offers::<T>::get(period).into_inner();
+            requests.sort_by_key(|r| r.price);
+            offers.sort_by_key(|o| o.price);
+            let cursor = ClearingCursor {
+                request_left: requests.first().map_or(0, |r| r.amount),
+                tariff,
+                ..Default::default()
+            };
+
+            Requests::<T>::insert(period, BoundedVec::truncate_from(requests));
+            Offers::<T>::insert(period, BoundedVec::truncate_from(offers));
+            cursor
+        }
+
+        /// Match requests and offers the way `energy_trade_matching::clear_peer_to_peer` does, in
+        /// whole units of price.
+        ///
+        /// Requests are served in ascending order of price, each buying from the offers cheapest to
+        /// it, once the premium it adds for a preferred source is deducted, at no more than its
+        /// price along with that premium, and never from an excluded source. Orders of equal price
+        /// are served in the order given. A trade is priced at the average of the request, with
+        /// the premium, and the offer, rounded down.
+        #[cfg(test)]
+        pub(crate) fn match_orders(requests: &[Order<T>], offers: &[Order<T>]) -> Vec<Trade<T>> {
+            let mut requests = requests.to_vec();
+            let mut offers = offers.to_vec();
+            requests.sort_by_key(|r| r.price);
+            offers.sort_by_key(|o| o.price);
+            let mut cursor = ClearingCursor {
+                request_left: requests.first().map_or(0, |r| r.amount),
+                ..Default::default()
+            };
+
+            let mut trades = Vec::new();
+            let on_trade = |trade, _| trades.push(trade);
+            let on_served = |_: &Order<T>, _, _| {};
+            Self::match_sorted(&requests, &mut offers, &mut cursor, u32::MAX, on_trade, on_served);
+            trades
+        }
+
+        /// Match orders sorted by price from the cursor on, the offers holding the amounts left of
+        /// them, passing on each trade with the certificate of the offer's source and each request
+        /// served along with the value of its trades and the amount it was left with, and return
+        /// the number of orders processed. Each step finishes a request, or an offer, or both.
+        fn match_sorted(
+            requests: &[Order<T>],
+            offers: &mut [Order<T>],
+            cursor: &mut ClearingCursor,
+            max_orders: u32,
+            mut on_trade: impl FnMut(Trade<T>, Certificate),
+            mut on_served: impl FnMut(&Order<T>, u32, u16),
+        ) -> u32 {
+            let mut processed = 0;
+            while processed < max_orders {
+                let r = match requests.get(cursor.next_request as usize) {
+                    Some(r) => r,
+                    None => break,
+                };
+                processed += 1;
+                let mut unserved = 0;
+
+                // Move on to the next request once no peer is willing to sell this cheaply
+                match Self::best_offer_for(r, offers) {
+                    Some(o) => {
+                        let amount = cursor.request_left.min(o.amount);
+                        if amount > 0 {
+                            let premium = r.preference.premium_for(o.source);
+                            let sum = r.price as u16 + premium as u16 + o.price as u16;
+                            let price = (sum / 2).min(u8::MAX.into()) as u8;
+                            cursor.request_spent += amount as u32 * price as u32;
+                            let trade = Trade {
+                                amount,
+                                price,
+                                seller: o.account.clone(),
+                                buyer: r.account.clone(),
+                            };
+                            on_trade(trade, Certificate { source: o.source, premium });
+                        }
+                        cursor.request_left -= amount;
+                        o.amount -= amount;
+                    },
+                    None => {
+                        unserved = cursor.request_left;
+                        cursor.request_left = 0;
+                    },
+                }
+                if cursor.request_left == 0 {
+                    on_served(r, cursor.request_spent, unserved);
+                    cursor.request_spent = 0;
+                    cursor.next_request += 1;
+                    cursor.request_left =
+                        requests.get(cursor.next_request as usize).map_or(0, |r| r.amount);
+                }
+            }
+
+            processed
+        }
+
+        /// The offer with an amount left that the request accepts and that is cheapest to it, once
+        /// the premium it adds for a preferred source is deducted, the earliest of offers equally
+        /// cheap, as `energy_trade_matching` picks offers.
+        fn best_offer_for<'a>(
+            r: &Order<T>,
+            offers: &'a mut [Order<T>],
+        ) -> Option<&'a mut Order<T>> {
+            offers
+                .iter_mut()
+                .filter(|o| o.amount > 0 && r.preference.accepts(r.price, o.source, o.price))
+                .min_by_key(|o| o.price as i16 - r.preference.premium_for(o.source) as i16)
+        }
+
+        /// Id of a trade formed in the given round after as many identical trades as given by the
+        /// occurrence: the BLAKE2b-256 hash of the SCALE encoded
+        /// `(round, occurrence, buyer, seller, amount, price)`, with the price in thousandths. As
+        /// the order trades are formed in is left out, the matching crate derives the same ids
+        /// for the same trades.
+        pub fn trade_id(round: u64, occurrence: u32, trade: &Trade<T>) -> TradeId {
+            let price = trade.price as u64 * PRICE_SCALE;
+            (round, occurrence, trade.buyer.encode(), trade.seller.encode(), trade.amount, price)
+                .using_encoded(blake2_256)
+        }
+
+        /// Store the trade along with the certificate of origin of its energy, as the next of the
+        /// trades of the period it was formed in, as long as the period has room for it.
+        pub fn record_trade(
+            round: u64,
+            trade: Trade<T>,
+            certificate: Certificate,
+        ) -> Result<TradeId, DispatchError> {
+            let mut ids = PeriodTrades::<T>::get(round);
+            let mut occurrence = 0;
+            let mut id = Self::trade_id(round, occurrence, &trade);
+            while ids.contains(&id) {
+                occurrence += 1;
+                id = Self::trade_id(round, occurrence, &trade);
+            }
+            ids.try_push(id).map_err(|_| Error::<T>::TooManyTrades)?;
+            PeriodTrades::<T>::insert(round, ids);
+
+            Self::deposit_event(Event::TradeAdded(
+                trade.amount,
+                trade.price,
+                trade.seller.clone(),
+                trade.buyer.clone(),
+                id,
+            ));
+            TradedEnergy::<T>::mutate(round, &trade.buyer, |net| {
+                *net = net.saturating_add(trade.amount.into())
+            });
+            TradedEnergy::<T>::mutate(round, &trade.seller, |net| {
+                *net = net.saturating_sub(trade.amount.into())
+            });
+            Trades::<T>::insert(id, trade);
+            Certificates::<T>::insert(id, certificate);
+
+            Ok(id)
+        }
+
+        /// Remove up to `limit` of the trades, certificates, traded energy, meter readings,
+        /// deliveries and failed settlements of the earliest period kept, once it is no longer
+        /// among the last `RetainedPeriods` periods cleared, moving on to the next period once
+        /// nothing of it is left.
+        pub(crate) fn prune(limit: u32) -> Weight {
+            let period = PrunedPeriod::<T>::get();
+            let retained = period.saturating_add(T::RetainedPeriods::get().into());
+            if retained >= CurrentPeriod::<T>::get() {
+                return T::DbWeight::get().reads(2)
+            }
+
+            let mut ids = PeriodTrades::<T>::get(period).into_inner();
+            let trades = ids.len().min(limit as usize);
+            for id in ids.drain(..trades) {
+                Trades::<T>::remove(id);
+                Certificates::<T>::remove(id);
+            }
+            let mut done = ids.is_empty();
+            if done {
+                PeriodTrades::<T>::remove(period);
+            } else {
+                PeriodTrades::<T>::insert(period, BoundedVec::truncate_from(ids));
+            }
+
+            let mut left = limit.saturating_sub(trades as u32);
+            let mut entries = 0;
+            for map in 0..4 {
+                if !done || left == 0 {
+                    done = false;
+                    break
+                }
+                let removal = match map {
+                    0 => TradedEnergy::<T>::clear_prefix(period, left, None),
+                    1 => MeterReadings::<T>::clear_prefix(period, left, None),
+                    2 => Deliveries::<T>::clear_prefix(period, left, None),
+                    _ => FailedSettlements::<T>::clear_prefix(period, left, None),
+                };
+                left = left.saturating_sub(removal.loops);
+                entries += removal.loops;
+                done = removal.maybe_cursor.is_none();
+            }
+            if done {
+                PrunedPeriod::<T>::put(period.saturating_add(1));
+            }
+
+            T::WeightInfo::prune_period(trades as u32, entries)
+        }
+
+        /// Add the reading of a meter of the participant to the energy they were metered with in
+        /// the period, and record how far it deviates from the energy they traded.
+        fn reconcile(participant: &T::AccountId, period: PeriodIndex, reading: &MeterReading) {
+            let traded = TradedEnergy::<T>::get(period, participant)
+                .saturating_mul(T::WattHoursPerUnit::get().into());
+            let delivery = Deliveries::<T>::mutate(period, participant, |delivery| {
+                let delivery = delivery.get_or_insert_with(Default::default);
+                delivery.traded_wh = traded;
+                delivery.metered_wh = delivery.metered_wh.saturating_add(reading.net_wh());
+                *delivery
+            });
+
+            Self::deposit_event(Event::DeliveryReconciled(
+                participant.clone(),
+                period,
+                delivery.deviation_wh(),
+            ));
+        }
+
+        /// Add the trade to the statements of its buyer and seller for the period being cleared.
+        fn add_to_statements(trade: &Trade<T>) {
+            let value = Self::trade_value(trade);
+            PeriodStatements::<T>::mutate(&trade.buyer, |buyer| {
+                buyer.energy_bought = buyer.energy_bought.saturating_add(trade.amount as u32);
+                buyer.value_bought = buyer.value_bought.saturating_add(value);
+            });
+            PeriodStatements::<T>::mutate(&trade.seller, |seller| {
+                seller.energy_sold = seller.energy_sold.saturating_add(trade.amount as u32);
+                seller.value_sold = seller.value_sold.saturating_add(value);
+            });
+        }
+
+        /// Settle the trades of the period from the statements of the participants, each paying
+        /// or receiving only the difference between the value of what they bought and sold.
+        ///
+        /// Those who owe pay from the reserve held for their requests, or from the free balance of
+        /// the grid operator when it owes, to those who are owed in turn. What is left reserved
+        /// for the energy they sold as well is released, and what could not be paid is recorded
+        /// with the reason.
+        fn settle_period(period: PeriodIndex) {
+            let grid = T::GridOperator::get();
+            let statements: Vec<_> = PeriodStatements::<T>::drain().collect();
+            // Participants owing and owed, with what is left to settle and what was settled
+            let mut payers = Vec::new();
+            let mut payees = Vec::new();
+            for (who, statement) in &statements {
+                let (bought, sold) = (statement.value_bought, statement.value_sold);
+                if bought > sold {
+                    payers.push((who, bought - sold, BalanceOf::<T>::zero()));
+                } else {
+                    payees.push((who, sold - bought, BalanceOf::<T>::zero()));
+                }
+            }
+
+            let (mut p, mut q) = (0, 0);
+            while let (Some(payer), Some(payee)) = (payers.get_mut(p), payees.get_mut(q)) {
+                let (from, owed, paid) = payer;
+                let (to, due, received) = payee;
+                if due.is_zero() {
+                    // Bought as much as they sold
+                    q += 1;
+                    continue
+                }
+                let value = (*owed).min(*due);
+                let result = if **from == grid {
+                    T::Currency::transfer(*from, *to, value, ExistenceRequirement::KeepAlive)
+                        .map(|_| Zero::zero())
+                } else {
+                    T::Currency::repatriate_reserved(*from, *to, value, BalanceStatus::Free)
+                };
+                let (unpaid, reason) = match result {
+                    Ok(unpaid) => (unpaid, SettlementFailure::InsufficientReserve),
+                    Err(_) => (value, SettlementFailure::TransferFailed),
+                };
+                if !unpaid.is_zero() {
+                    FailedSettlements::<T>::mutate(period, *from, |failure| {
+                        let unpaid = failure
+                            .as_ref()
+                            .map_or(unpaid, |failure| failure.unpaid.saturating_add(unpaid));
+                        *failure = Some(FailedSettlement { reason, unpaid });
+                    });
+                    Self::deposit_event(Event::SettlementFailed(
+                        (*from).clone(),
+                        period,
+                        reason,
+                        unpaid,
+                    ));
+                }
+
+                *owed -= value;
+                *due -= value;
+                *paid = paid.saturating_add(value.saturating_sub(unpaid));
+                *received = received.saturating_add(value.saturating_sub(unpaid));
+                if owed.is_zero() {
+                    p += 1;
+                }
+                if due.is_zero() {
+                    q += 1;
+                }
+            }
+
+            for (who, statement) in &statements {
+                if *who != grid {
+                    T::Currency::unreserve(who, statement.value_bought.min(statement.value_sold));
+                }
+            }
+            for (who, _, paid) in payers {
+                Self::deposit_event(Event::TradesSettled(who.clone(), paid, Zero::zero()));
+            }
+            for (who, _, received) in payees {
+                Self::deposit_event(Event::TradesSettled(who.clone(), Zero::zero(), received));
+            }
+        }
+
+        /// Amount of money changing hands in the trade.
+        pub fn trade_value(trade: &Trade<T>) -> BalanceOf<T> {
+            BalanceOf::<T>::from(trade.amount as u32)
+                .saturating_mul(BalanceOf::<T>::from(trade.price as u32))
+        }
     }
 
-    #[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo)]
+    #[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
     #[scale_info(skip_type_params(T))]
     pub struct Trade<T: Config> {
         pub amount: u16,
@@ -81,4 +1391,230 @@ pub mod pallet {
         pub seller: <T as frame_system::Config>::AccountId,
         pub buyer: <T as frame_system::Config>::AccountId,
     }
+
+    /// A request to buy or an offer to sell energy in a period
+    #[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    #[scale_info(skip_type_params(T))]
+    pub struct Order<T: Config> {
+        pub id: OrderId,
+        pub account: <T as frame_system::Config>::AccountId,
+        pub amount: u16,
+        /// Highest price per unit for a request, lowest for an offer
+        pub price: u8,
+        /// How the energy of an offer is generated, left at the default for a request
+        pub source: EnergySource,
+        /// Sources a request prefers or excludes, left at the default for an offer
+        pub preference: SourcePreference,
+    }
+
+    /// The book an order is placed in
+    #[derive(Clone, Copy, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub enum OrderSide {
+        Request,
+        Offer,
+    }
+
+    /// Position of clearing in the orders of a period sorted by price
+    #[derive(
+        Clone, Copy, Default, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen,
+    )]
+    pub struct ClearingCursor {
+        /// Index of the request being served
+        pub next_request: u32,
+        /// Amount the request has left to buy
+        pub request_left: u16,
+        /// Value of the trades of the request so far
+        pub request_spent: u32,
+        /// Index of the offer sold to the grid next, once all requests are served
+        pub next_offer: u32,
+        /// Tariff of the grid in the period, with the capacity it has left
+        pub tariff: GridTariff,
+    }
+
+    /// Prices and capacities of trading with the grid in a period
+    #[derive(
+        Clone, Copy, Default, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen,
+    )]
+    pub struct GridTariff {
+        /// Price per unit of energy bought from the grid
+        pub import_price: u8,
+        /// Price per unit of energy sold to the grid
+        pub export_price: u8,
+        /// Most energy the grid supplies in a period
+        pub import_capacity: u16,
+        /// Most energy the grid takes in a period
+        pub export_capacity: u16,
+    }
+
+    /// How the energy of a trade was generated, as in `energy_trade_matching::EnergySource`
+    #[derive(
+        Clone, Copy, Default, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen,
+    )]
+    pub enum EnergySource {
+        Pv,
+        Wind,
+        Chp,
+        #[default]
+        GridMix,
+    }
+
+    /// Sources a request prefers or excludes, by default none, as in
+    /// `energy_trade_matching::SourcePreference`
+    #[derive(Clone, Default, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct SourcePreference {
+        pub preferred: BoundedVec<EnergySource, ConstU32<4>>,
+        /// Added per unit to the price of the request when buying from a preferred source
+        pub premium: u8,
+        /// Sources the request never buys from among peers
+        pub excluded: BoundedVec<EnergySource, ConstU32<4>>,
+    }
+
+    impl SourcePreference {
+        /// Premium the request adds to its price for energy of the source.
+        pub fn premium_for(&self, source: EnergySource) -> u8 {
+            if self.preferred.contains(&source) {
+                self.premium
+            } else {
+                0
+            }
+        }
+
+        /// Premium the request adds to its price for energy of any preferred source.
+        pub fn max_premium(&self) -> u8 {
+            if self.preferred.is_empty() {
+                0
+            } else {
+                self.premium
+            }
+        }
+
+        /// Whether the request buys energy of the source at the price of an offer.
+        pub fn accepts(&self, price: u8, source: EnergySource, offer_price: u8) -> bool {
+            let limit = price as u16 + self.premium_for(source) as u16;
+            !self.excluded.contains(&source) && limit >= offer_price as u16
+        }
+    }
+
+    /// Certificate of origin of the energy of a trade
+    #[derive(
+        Clone, Copy, Default, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen,
+    )]
+    pub struct Certificate {
+        pub source: EnergySource,
+        /// Premium per unit the buyer paid for the source, included in the trade's price
+        pub premium: u8,
+    }
+
+    /// Why a participant could not pay in full what they owed
+    #[derive(Clone, Copy, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub enum SettlementFailure {
+        /// The reserve of the participant did not cover what they owed
+        InsufficientReserve,
+        /// The grid operator could not pay the seller
+        TransferFailed,
+    }
+
+    /// What a participant could not pay of what they owed for a period
+    #[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct FailedSettlement<Balance> {
+        pub reason: SettlementFailure,
+        /// Amount those owed by the participant were not paid
+        pub unpaid: Balance,
+    }
+
+    /// What a participant does in the market
+    #[derive(Clone, Copy, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+    pub enum Role {
+        Consumer,
+        Producer,
+        Prosumer,
+        Storage,
+        MicrogridAggregator,
+        GridOperator,
+    }
+
+    impl Role {
+        /// Whether only `GridOrigin` or root can grant the role, rather than accounts registering
+        /// in it themselves.
+        pub fn is_privileged(&self) -> bool {
+            matches!(self, Role::MicrogridAggregator | Role::GridOperator)
+        }
+
+        /// Whether participants in the role place orders of the side: consumers only buy and
+        /// producers only sell.
+        pub fn places(&self, side: OrderSide) -> bool {
+            match self {
+                Role::Consumer => side == OrderSide::Request,
+                Role::Producer => side == OrderSide::Offer,
+                _ => true,
+            }
+        }
+    }
+
+    /// A registered participant of the market
+    #[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct Participant<Balance> {
+        pub role: Role,
+        /// Microgrid the participant is connected through, if any
+        pub microgrid: Option<MicrogridId>,
+        /// Meter of the participant's connection to the grid
+        pub metering_point: MeterId,
+        /// Whether the participant may place orders
+        pub active: bool,
+        /// Amount reserved from the participant while they are registered
+        pub deposit: Balance,
+        /// Orders of the participant that are neither cancelled nor cleared yet
+        pub open_orders: u32,
+    }
+
+    /// The signing key of a smart meter and the participant whose energy it measures
+    #[derive(Clone, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct Meter<AccountId> {
+        pub key: AccountId,
+        pub participant: AccountId,
+    }
+
+    /// Energy a meter measured over a period
+    #[derive(Clone, Copy, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct MeterReading {
+        /// Energy drawn from the grid
+        pub imported_wh: u64,
+        /// Energy fed into the grid
+        pub exported_wh: u64,
+    }
+
+    impl MeterReading {
+        /// Energy imported less energy exported
+        pub fn net_wh(&self) -> i64 {
+            let imported: i64 = self.imported_wh.saturated_into();
+            imported.saturating_sub(self.exported_wh.saturated_into())
+        }
+    }
+
+    /// Energy a participant traded in a period against the energy their meters measured, both
+    /// counting imports positively and exports negatively
+    #[derive(
+        Clone, Copy, Default, Encode, Decode, PartialEq, Eq, RuntimeDebug, TypeInfo, MaxEncodedLen,
+    )]
+    pub struct Delivery {
+        pub traded_wh: i64,
+        pub metered_wh: i64,
+    }
+
+    impl Delivery {
+        /// Energy metered beyond what was traded, negative when less was delivered
+        pub fn deviation_wh(&self) -> i64 {
+            self.metered_wh.saturating_sub(self.traded_wh)
+        }
+    }
+
+    /// Energy an account bought and sold in a period, and the value of it at the prices traded
+    #[derive(Clone, Default, Encode, Decode, PartialEq, RuntimeDebug, TypeInfo, MaxEncodedLen)]
+    pub struct Statement<Balance> {
+        pub energy_bought: u32,
+        pub energy_sold: u32,
+        pub value_bought: Balance,
+        pub value_sold: Balance,
+    }
 }
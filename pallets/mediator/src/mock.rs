@@ -1,6 +1,11 @@
 use crate as pallet_mediator;
-use frame_support::{parameter_types, traits::Everything};
+use frame_support::{
+	ord_parameter_types, parameter_types,
+	traits::{ConstU16, ConstU32, ConstU64, ConstU8, Everything},
+};
 use frame_system as system;
+use frame_system::EnsureSignedBy;
+use pallet_mediator::Role;
 use sp_core::H256;
 use sp_runtime::{
 	testing::Header,
@@ -18,13 +23,20 @@ frame_support::construct_runtime!(
 		UncheckedExtrinsic = UncheckedExtrinsic,
 	{
 		System: frame_system::{Pallet, Call, Config, Storage, Event<T>},
-		MediatorModule: pallet_mediator::{Pallet, Call, Storage, Event<T>},
+		Balances: pallet_balances::{Pallet, Call, Storage, Config<T>, Event<T>},
+		MediatorModule: pallet_mediator::{Pallet, Call, Storage, Config<T>, Event<T>},
 	}
 );
 
 parameter_types! {
 	pub const BlockHashCount: u64 = 250;
 	pub const SS58Prefix: u8 = 42;
+	/// No deposit is reserved for orders, unless a test sets one
+	pub static OrderDeposit: u64 = 0;
+}
+
+ord_parameter_types! {
+	pub const GridOperatorAccount: u64 = 100;
 }
 
 impl system::Config for Test {
@@ -45,7 +57,7 @@ impl system::Config for Test {
 	type BlockHashCount = BlockHashCount;
 	type Version = ();
 	type PalletInfo = PalletInfo;
-	type AccountData = ();
+	type AccountData = pallet_balances::AccountData<u64>;
 	type OnNewAccount = ();
 	type OnKilledAccount = ();
 	type SystemWeightInfo = ();
@@ -54,11 +66,60 @@ impl system::Config for Test {
 	type MaxConsumers = frame_support::traits::ConstU32<16>;
 }
 
+impl pallet_balances::Config for Test {
+	type MaxLocks = ();
+	type MaxReserves = ();
+	type ReserveIdentifier = [u8; 8];
+	type Balance = u64;
+	type RuntimeEvent = RuntimeEvent;
+	type DustRemoval = ();
+	type ExistentialDeposit = ConstU64<1>;
+	type AccountStore = System;
+	type WeightInfo = ();
+}
+
 impl pallet_mediator::Config for Test {
 	type RuntimeEvent = RuntimeEvent;
+	type Currency = Balances;
+	type MaxOrdersPerPeriod = ConstU32<3>;
+	type MaxOrdersPerAccount = ConstU32<2>;
+	type MaxPeriodsAhead = ConstU64<8>;
+	type OrderDeposit = OrderDeposit;
+	type MaxTradesPerPeriod = ConstU32<12>;
+	type MaxParticipants = ConstU32<6>;
+	type MinOrderAmount = ConstU16<1>;
+	type MaxOrderAmount = ConstU16<1_000>;
+	type MinPrice = ConstU8<1>;
+	type MaxPrice = ConstU8<100>;
+	type PeriodLength = ConstU64<10>;
+	type GateClosureOffset = ConstU64<2>;
+	type MaxOrdersPerBlock = ConstU32<2>;
+	type GridOperator = ConstU64<100>;
+	type WattHoursPerUnit = ConstU32<1_000>;
+	type RegistrationDeposit = ConstU64<50>;
+	type RetainedPeriods = ConstU32<2>;
+	type MaxPrunedPerBlock = ConstU32<4>;
+	type GridOrigin = EnsureSignedBy<GridOperatorAccount, u64>;
+	type WeightInfo = ();
 }
 
 // Build genesis storage according to the mock runtime.
 pub fn new_test_ext() -> sp_io::TestExternalities {
-	system::GenesisConfig::default().build_storage::<Test>().unwrap().into()
+	let mut storage = system::GenesisConfig::default().build_storage::<Test>().unwrap();
+	// The grid operator, 100, is endowed as the chain spec endows it, to pay for what it buys
+	pallet_balances::GenesisConfig::<Test> {
+		balances: [1, 2, 3, 4, 6, 100].into_iter().map(|who| (who, 1_000)).collect(),
+	}
+	.assimilate_storage(&mut storage)
+	.unwrap();
+	pallet_mediator::GenesisConfig::<Test> {
+		participants: (1..=5).map(|who| (who, Role::Prosumer, None, who)).collect(),
+	}
+	.assimilate_storage(&mut storage)
+	.unwrap();
+
+	let mut ext: sp_io::TestExternalities = storage.into();
+	// Events are not deposited in the genesis block
+	ext.execute_with(|| System::set_block_number(1));
+	ext
 }
@@ -1,23 +1,964 @@
-use crate::{mock::*, Error};
-use frame_support::{assert_noop, assert_ok};
+use crate::{
+	mock::*, Certificate, Delivery, EnergySource, Error, Event, FailedSettlement, GridTariff,
+	Meter, MeterReading, Order, Participant, Role, SettlementFailure, SourcePreference, Trade,
+};
+use frame_support::{
+	assert_noop, assert_ok,
+	pallet_prelude::BoundedVec,
+	traits::{BalanceStatus, Currency, Hooks, ReservableCurrency},
+};
+use sp_runtime::{
+	traits::{BlakeTwo256, Hash},
+	DispatchError, DispatchResult,
+};
+
+fn trade(amount: u16, price: u8, seller: u64, buyer: u64) -> Trade<Test> {
+	Trade { amount, price, seller, buyer }
+}
+
+fn submit_pv_offer(who: u64, amount: u16, price: u8, period: u64) -> DispatchResult {
+	MediatorModule::submit_offer(
+		RuntimeOrigin::signed(who),
+		amount,
+		price,
+		period,
+		EnergySource::Pv,
+	)
+}
+
+fn submit_request(who: u64, amount: u16, price: u8, period: u64) -> DispatchResult {
+	let origin = RuntimeOrigin::signed(who);
+	MediatorModule::submit_request(origin, amount, price, period, Default::default())
+}
+
+fn amend_order(who: u64, order_id: u64, amount: u16, price: u8) -> DispatchResult {
+	let origin = RuntimeOrigin::signed(who);
+	MediatorModule::amend_order(origin, order_id, amount, price, Default::default())
+}
+
+fn run_to_block(n: u64) {
+	while System::block_number() < n {
+		System::set_block_number(System::block_number() + 1);
+		MediatorModule::on_initialize(System::block_number());
+	}
+}
+
+#[test]
+fn trades_are_recorded_with_their_certificate() {
+	new_test_ext().execute_with(|| {
+		let certificate = Certificate { source: EnergySource::Pv, premium: 1 };
+
+		let id = MediatorModule::record_trade(7, trade(10, 3, 1, 2), certificate).unwrap();
+
+		assert_eq!(MediatorModule::trades(id), Some(trade(10, 3, 1, 2)));
+		assert_eq!(MediatorModule::certificates(id), Some(certificate));
+		System::assert_last_event(Event::<Test>::TradeAdded(10, 3, 1, 2, id).into());
+
+		// A period holds up to `MaxTradesPerPeriod` trades
+		for _ in 1..12 {
+			assert_ok!(MediatorModule::record_trade(7, trade(10, 3, 1, 2), certificate));
+		}
+		assert_noop!(
+			MediatorModule::record_trade(7, trade(10, 3, 1, 2), certificate),
+			Error::<Test>::TooManyTrades
+		);
+	});
+}
+
+#[test]
+fn trade_ids_match_the_off_chain_test_vector() {
+	new_test_ext().execute_with(|| {
+		// Checked by `energy_trade_matching::trade_id` as well
+		let test_vector = hex_literal::hex!(
+			"d20f64dddd0eb9291934d60910feac6377047555a6749fab4c3dd4c7478e3abe"
+		);
+
+		assert_eq!(MediatorModule::trade_id(7, 0, &trade(10, 3, 1, 2)), test_vector);
+	});
+}
+
+#[test]
+fn submitted_orders_are_stored_by_period() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(submit_request(1, 10, 3, 7));
+		assert_ok!(submit_pv_offer(2, 8, 2, 7));
+		assert_ok!(submit_pv_offer(3, 5, 2, 8));
+
+		assert_eq!(
+			MediatorModule::requests(7).into_inner(),
+			vec![Order {
+				id: 0,
+				account: 1,
+				amount: 10,
+				price: 3,
+				source: Default::default(),
+				preference: Default::default(),
+			}]
+		);
+		assert_eq!(
+			MediatorModule::offers(7).into_inner(),
+			vec![Order {
+				id: 1,
+				account: 2,
+				amount: 8,
+				price: 2,
+				source: EnergySource::Pv,
+				preference: Default::default(),
+			}]
+		);
+		assert_eq!(MediatorModule::offers(8).len(), 1);
+		System::assert_has_event(Event::<Test>::RequestSubmitted(1, 0, 7, 10, 3).into());
+		System::assert_last_event(Event::<Test>::OfferSubmitted(3, 2, 8, 5, 2).into());
+	});
+}
+
+#[test]
+fn orders_out_of_bounds_are_rejected() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(submit_request(1, 0, 3, 7), Error::<Test>::AmountOutOfBounds);
+		assert_noop!(submit_pv_offer(1, 1_001, 3, 7), Error::<Test>::AmountOutOfBounds);
+		assert_noop!(submit_request(1, 10, 101, 7), Error::<Test>::PriceOutOfBounds);
+
+		for who in 1..=3 {
+			assert_ok!(submit_pv_offer(who, 10, 3, 7));
+		}
+		assert_noop!(submit_pv_offer(4, 10, 3, 7), Error::<Test>::TooManyOrders);
+		assert_ok!(submit_request(4, 10, 3, 7));
+	});
+}
+
+#[test]
+fn accounts_hold_a_limited_number_of_orders_per_period() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(submit_pv_offer(1, 10, 3, 1));
+		assert_ok!(submit_pv_offer(1, 5, 4, 1));
+		assert_noop!(submit_pv_offer(1, 5, 5, 1), Error::<Test>::TooManyAccountOrders);
+
+		// The limit holds for each side of the book and each period
+		assert_ok!(submit_request(1, 10, 3, 1));
+		assert_ok!(submit_pv_offer(1, 5, 5, 2));
+		// Cancelled orders no longer count
+		assert_ok!(MediatorModule::cancel_order(RuntimeOrigin::signed(1), 0));
+		assert_ok!(submit_pv_offer(1, 5, 5, 1));
+	});
+}
+
+#[test]
+fn orders_are_placed_a_limited_number_of_periods_ahead() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(submit_request(1, 10, 3, 9));
+		assert_noop!(submit_request(1, 10, 3, 10), Error::<Test>::PeriodTooFarAhead);
+		assert_noop!(submit_pv_offer(2, 10, 3, 10), Error::<Test>::PeriodTooFarAhead);
+
+		// Once period 1 is cleared, period 10 is no more than 8 periods ahead
+		run_to_block(8);
+		assert_eq!(MediatorModule::current_period(), 2);
+		assert_ok!(submit_pv_offer(2, 10, 3, 10));
+	});
+}
+
+#[test]
+fn orders_reserve_a_deposit_until_cancelled_or_cleared() {
+	new_test_ext().execute_with(|| {
+		OrderDeposit::set(5);
+		// 5 has no funds to reserve the deposit of an offer from
+		assert_noop!(submit_pv_offer(5, 10, 3, 1), Error::<Test>::InsufficientFunds);
+
+		assert_ok!(submit_pv_offer(3, 10, 2, 1));
+		assert_ok!(submit_pv_offer(3, 4, 2, 1));
+		assert_eq!(Balances::reserved_balance(3), 10);
+		assert_ok!(submit_request(1, 10, 3, 1));
+		assert_eq!(Balances::reserved_balance(1), 35);
+
+		assert_ok!(MediatorModule::cancel_order(RuntimeOrigin::signed(3), 1));
+		assert_eq!(Balances::reserved_balance(3), 5);
+
+		run_to_block(9);
+		System::assert_has_event(Event::<Test>::PeriodCleared(1).into());
+		assert_eq!(Balances::reserved_balance(1), 0);
+		assert_eq!(Balances::reserved_balance(3), 0);
+		assert_eq!(Balances::free_balance(1), 980);
+		assert_eq!(Balances::free_balance(3), 1_020);
+	});
+}
+
+#[test]
+fn organize_trades_clears_the_current_period_among_peers() {
+	new_test_ext().execute_with(|| {
+		// Period 0 closed at genesis, without orders
+		run_to_block(2);
+		assert_ok!(submit_request(1, 10, 3, 1));
+		assert_ok!(submit_request(2, 5, 2, 1));
+		assert_ok!(submit_pv_offer(3, 8, 2, 1));
+		assert_ok!(submit_pv_offer(4, 10, 4, 1));
+		assert_noop!(
+			MediatorModule::organize_trades(RuntimeOrigin::signed(1)),
+			Error::<Test>::GateOpen
+		);
+
+		System::set_block_number(8);
+		let info = MediatorModule::organize_trades(RuntimeOrigin::signed(1)).unwrap();
+		// Charged for the orders processed rather than for the largest book possible
+		let actual = info.actual_weight.unwrap();
+		assert!(actual.all_lt(MediatorModule::max_clearing_weight()));
+
+		// The cheaper request is served first, and the offer at 4 is too expensive for either
+		let first = MediatorModule::trade_id(1, 0, &trade(5, 2, 3, 2));
+		let second = MediatorModule::trade_id(1, 0, &trade(3, 2, 3, 1));
+		assert_eq!(MediatorModule::trades(first), Some(trade(5, 2, 3, 2)));
+		assert_eq!(MediatorModule::trades(second), Some(trade(3, 2, 3, 1)));
+		System::assert_has_event(Event::<Test>::TradeAdded(5, 2, 3, 2, first).into());
+		System::assert_has_event(Event::<Test>::TradeAdded(3, 2, 3, 1, second).into());
+		assert_eq!(MediatorModule::period_trades(1).into_inner(), vec![first, second]);
+		let pv = Certificate { source: EnergySource::Pv, premium: 0 };
+		assert_eq!(MediatorModule::certificates(first), Some(pv));
+		System::assert_last_event(Event::<Test>::PeriodCleared(1).into());
+		assert!(MediatorModule::requests(1).is_empty());
+		assert_eq!(MediatorModule::current_period(), 2);
+		assert_noop!(submit_request(1, 10, 3, 2), Error::<Test>::GateClosed);
+	});
+}
+
+#[test]
+fn identical_trades_of_a_period_get_distinct_ids() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(submit_request(1, 5, 3, 1));
+		assert_ok!(submit_request(1, 5, 3, 1));
+		assert_ok!(submit_pv_offer(3, 10, 3, 1));
+
+		run_to_block(8);
+
+		let ids = MediatorModule::period_trades(1).into_inner();
+		let identical = trade(5, 3, 3, 1);
+		let expected: Vec<_> = (0..2).map(|n| MediatorModule::trade_id(1, n, &identical)).collect();
+		assert_eq!(ids, expected);
+		assert_ne!(ids[0], ids[1]);
+		for id in ids {
+			assert_eq!(MediatorModule::trades(id), Some(trade(5, 3, 3, 1)));
+		}
+	});
+}
+
+#[test]
+fn periods_are_cleared_at_gate_closure_over_several_blocks() {
+	new_test_ext().execute_with(|| {
+		for (who, price) in [(1, 3), (2, 4), (3, 5)] {
+			assert_ok!(submit_request(who, 5, price, 1));
+		}
+		assert_ok!(submit_pv_offer(4, 6, 2, 1));
+		assert_ok!(submit_pv_offer(5, 10, 4, 1));
+
+		run_to_block(7);
+		assert_eq!(MediatorModule::current_period(), 1);
+		assert!(MediatorModule::clearing().is_none());
+
+		// Two orders are processed per block
+		run_to_block(8);
+		for trade in [trade(5, 2, 4, 1), trade(1, 3, 4, 2)] {
+			let id = MediatorModule::trade_id(1, 0, &trade);
+			assert_eq!(MediatorModule::trades(id), Some(trade));
+		}
+		assert!(MediatorModule::clearing().is_some());
+		assert_noop!(submit_pv_offer(4, 6, 2, 1), Error::<Test>::GateClosed);
+
+		run_to_block(9);
+		for trade in [trade(4, 4, 5, 2), trade(5, 4, 5, 3)] {
+			let id = MediatorModule::trade_id(1, 0, &trade);
+			assert_eq!(MediatorModule::trades(id), Some(trade));
+		}
+		System::assert_last_event(Event::<Test>::PeriodCleared(1).into());
+		assert!(MediatorModule::clearing().is_none());
+		assert_eq!(MediatorModule::current_period(), 2);
+	});
+}
+
+#[test]
+fn trades_are_priced_at_the_average_rounded_down() {
+	let orders = |prices: &[(u64, u8)]| {
+		let order = |&(account, price): &(u64, u8)| Order {
+			id: 0,
+			account,
+			amount: 10,
+			price,
+			source: Default::default(),
+			preference: Default::default(),
+		};
+		prices.iter().map(order).collect::<Vec<_>>()
+	};
+
+	let trades = MediatorModule::match_orders(&orders(&[(1, 5)]), &orders(&[(2, 2), (3, 6)]));
+
+	assert_eq!(trades, vec![trade(10, 3, 2, 1)]);
+}
+
+#[test]
+fn orders_are_matched_like_off_chain_peer_to_peer_clearing() {
+	use energy_trade_matching::energy_trade_matching::{
+		clear_peer_to_peer, EnergyOffer, EnergyRequest,
+	};
+
+	// Whole unit prices whose averages are whole as well, so that no rounding is involved
+	let requests = [(1, 10, 6), (2, 5, 2), (3, 8, 4), (4, 4, 6)];
+	let offers = [(5, 6, 2), (6, 10, 4), (7, 3, 2), (8, 7, 8)];
+	let order = |&(account, amount, price): &(u64, u16, u8)| Order {
+		id: 0,
+		account,
+		amount,
+		price,
+		source: Default::default(),
+		preference: Default::default(),
+	};
+	let mut off_chain_requests: Vec<_> = requests
+		.iter()
+		.map(|&(buyer, amount, price)| EnergyRequest {
+			amount,
+			price: price as f32,
+			buyer: buyer.to_string(),
+			..Default::default()
+		})
+		.collect();
+	let mut off_chain_offers: Vec<_> = offers
+		.iter()
+		.map(|&(seller, amount, price)| EnergyOffer {
+			amount,
+			price: price as f32,
+			seller: seller.to_string(),
+			..Default::default()
+		})
+		.collect();
+
+	let trades = MediatorModule::match_orders(
+		&requests.iter().map(order).collect::<Vec<_>>(),
+		&offers.iter().map(order).collect::<Vec<_>>(),
+	);
+	let clearing = clear_peer_to_peer(&mut off_chain_requests, &mut off_chain_offers);
+
+	let account = |id: &str| id.parse().unwrap();
+	let off_chain_trades: Vec<_> = clearing
+		.trades
+		.iter()
+		.map(|t| trade(t.amount, t.price as u8, account(&t.seller), account(&t.buyer)))
+		.collect();
+	assert!(!trades.is_empty());
+	assert_eq!(trades, off_chain_trades);
+}
+
+#[test]
+fn requests_buy_preferred_sources_at_a_premium_like_off_chain_clearing() {
+	use energy_trade_matching::energy_trade_matching::{
+		clear_peer_to_peer, EnergyOffer, EnergyRequest, EnergySource as Source,
+		SourcePreference as Preference,
+	};
+
+	// PV is the cheapest with the premium deducted, although the dearest, and CHP is excluded.
+	// Price sums are even, so that no rounding is involved.
+	let offers = [
+		(2, EnergySource::Chp, Source::Chp, 2),
+		(3, EnergySource::Wind, Source::Wind, 4),
+		(4, EnergySource::Pv, Source::Pv, 6),
+	];
+	new_test_ext().execute_with(|| {
+		let preference = SourcePreference {
+			preferred: BoundedVec::truncate_from(vec![EnergySource::Pv]),
+			premium: 4,
+			excluded: BoundedVec::truncate_from(vec![EnergySource::Chp]),
+		};
+		let origin = RuntimeOrigin::signed(1);
+		assert_ok!(MediatorModule::submit_request(origin, 10, 6, 1, preference));
+		for (who, source, _, price) in offers {
+			let origin = RuntimeOrigin::signed(who);
+			assert_ok!(MediatorModule::submit_offer(origin, 5, price, 1, source));
+		}
+		// The request reserves its value at the premium
+		assert_eq!(Balances::reserved_balance(1), 100);
+
+		run_to_block(10);
+
+		let trades: Vec<_> = MediatorModule::period_trades(1)
+			.into_iter()
+			.map(|id| (MediatorModule::trades(id).unwrap(), MediatorModule::certificates(id)))
+			.filter(|(t, _)| t.buyer == 1)
+			.collect();
+
+		let origin =
+			Preference { preferred: vec![Source::Pv], premium: 4.0, excluded: vec![Source::Chp] };
+		let mut off_chain_requests = [EnergyRequest {
+			amount: 10,
+			price: 6.0,
+			buyer: "1".into(),
+			origin,
+			..Default::default()
+		}];
+		let mut off_chain_offers: Vec<_> = offers
+			.iter()
+			.map(|&(seller, _, source, price)| EnergyOffer {
+				amount: 5,
+				price: price as f32,
+				seller: seller.to_string(),
+				source,
+				..Default::default()
+			})
+			.collect();
+		let clearing = clear_peer_to_peer(&mut off_chain_requests, &mut off_chain_offers);
+		let off_chain_trades: Vec<_> = clearing
+			.trades
+			.iter()
+			.map(|t| {
+				let seller = t.seller.parse().unwrap();
+				let &(_, source, _, _) = offers.iter().find(|o| o.0 == seller).unwrap();
+				let certificate = Certificate { source, premium: t.certificate.premium as u8 };
+				(trade(t.amount, t.price as u8, seller, 1), Some(certificate))
+			})
+			.collect();
+		assert_eq!(
+			trades,
+			vec![
+				(trade(5, 8, 4, 1), Some(Certificate { source: EnergySource::Pv, premium: 4 })),
+				(trade(5, 5, 3, 1), Some(Certificate { source: EnergySource::Wind, premium: 0 })),
+			]
+		);
+		assert_eq!(trades, off_chain_trades);
+	});
+}
+
+#[test]
+fn only_requests_have_a_source_preference() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(submit_pv_offer(2, 8, 2, 1));
+		let preference = SourcePreference {
+			premium: 1,
+			preferred: BoundedVec::truncate_from(vec![EnergySource::Pv]),
+			..Default::default()
+		};
+
+		assert_noop!(
+			MediatorModule::amend_order(RuntimeOrigin::signed(2), 0, 8, 2, preference),
+			Error::<Test>::PreferenceOfOffer
+		);
+	});
+}
+
+#[test]
+fn trade_ids_match_those_of_off_chain_clearing_with_the_grid() {
+	use codec::Encode;
+	use energy_trade_matching::{
+		energy_trade_matching::{generate_trades, EnergyOffer, EnergyRequest},
+		trade_id::TradeId,
+	};
+
+	// Account ids as the matching crate takes them, SCALE encoded in hex
+	let participant = |who: u64| {
+		let encoded: String = who.encode().iter().map(|b| format!("{:02x}", b)).collect();
+		format!("0x{}", encoded)
+	};
+	let request = |buyer, amount, price: u8| EnergyRequest {
+		amount,
+		price: price as f32,
+		buyer: participant(buyer),
+		..Default::default()
+	};
+	let offer = |seller, amount, price: u8| EnergyOffer {
+		amount,
+		price: price as f32,
+		seller: participant(seller),
+		..Default::default()
+	};
+	let tariff =
+		GridTariff { import_price: 3, export_price: 2, import_capacity: 100, export_capacity: 100 };
+	let grid_request = EnergyRequest { price: 2.0, ..request(100, 0, 0) };
+	let grid_offer = EnergyOffer { price: 3.0, ..offer(100, 0, 0) };
+	let sorted = |mut ids: Vec<_>| {
+		ids.sort();
+		ids
+	};
+
+	new_test_ext().execute_with(|| {
+		assert_ok!(MediatorModule::set_grid_tariff(RuntimeOrigin::signed(100), tariff, None));
+		// 2 buys from 3 and the grid, 1 buys the same amount from the grid twice, the grid
+		// imports being formed between peer trades on-chain but after all of them off-chain
+		let requests = [(2, 10, 3), (1, 2, 4), (1, 2, 4)];
+		let offers = [(3, 4, 3)];
+		// 3 sells to 1 and what is left of its offer to the grid
+		let exports = ([(1, 2, 4)], [(3, 5, 2)]);
+
+		for (period, requests, offers) in
+			[(1, &requests[..], &offers[..]), (2, &exports.0[..], &exports.1[..])]
+		{
+			for &(who, amount, price) in requests {
+				assert_ok!(submit_request(who, amount, price, period));
+			}
+			for &(who, amount, price) in offers {
+				assert_ok!(submit_pv_offer(who, amount, price, period));
+			}
+			run_to_block(period * 10);
+
+			let trades = generate_trades(
+				&mut requests.iter().map(|&(who, a, p)| request(who, a, p)).collect::<Vec<_>>(),
+				&mut offers.iter().map(|&(who, a, p)| offer(who, a, p)).collect::<Vec<_>>(),
+				&grid_request,
+				&grid_offer,
+			);
+			let ids = MediatorModule::period_trades(period).into_inner();
+			let grid = participant(100);
+			assert!(trades.iter().any(|t| t.buyer == grid || t.seller == grid));
+			assert_eq!(
+				sorted(ids.into_iter().map(TradeId).collect()),
+				sorted(TradeId::of_round(period, &trades))
+			);
+		}
+	});
+}
+
+#[test]
+fn owners_can_amend_and_cancel_orders_until_gate_closure() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(submit_request(1, 10, 3, 1));
+		assert_ok!(submit_pv_offer(2, 8, 2, 1));
+
+		assert_noop!(amend_order(2, 0, 20, 4), Error::<Test>::NotOwner);
+		assert_noop!(amend_order(1, 0, 20, 101), Error::<Test>::PriceOutOfBounds);
+		assert_ok!(amend_order(1, 0, 20, 4));
+		assert_eq!(
+			MediatorModule::requests(1).into_inner(),
+			vec![Order {
+				id: 0,
+				account: 1,
+				amount: 20,
+				price: 4,
+				source: Default::default(),
+				preference: Default::default(),
+			}]
+		);
+		// Owners can follow their orders by the topic of their account
+		let last = System::events().pop().unwrap();
+		assert_eq!(last.event, Event::<Test>::OrderAmended(1, 0, 20, 4).into());
+		assert_eq!(last.topics, vec![BlakeTwo256::hash_of(&1u64)]);
+		// Offers keep the source of their energy
+		assert_ok!(amend_order(2, 1, 6, 2));
+		assert_eq!(MediatorModule::offers(1)[0].source, EnergySource::Pv);
+
+		assert_ok!(MediatorModule::cancel_order(RuntimeOrigin::signed(1), 0));
+		assert!(MediatorModule::requests(1).is_empty());
+		assert_eq!(MediatorModule::order_location(0), None);
+		System::assert_last_event(Event::<Test>::OrderCancelled(1, 0).into());
+		assert_noop!(
+			MediatorModule::cancel_order(RuntimeOrigin::signed(1), 0),
+			Error::<Test>::UnknownOrder
+		);
+
+		// The gate of period 1 closes at block 8
+		System::set_block_number(8);
+		assert_noop!(
+			MediatorModule::cancel_order(RuntimeOrigin::signed(2), 1),
+			Error::<Test>::GateClosed
+		);
+		assert_noop!(amend_order(2, 1, 8, 1), Error::<Test>::GateClosed);
+	});
+}
+
+#[test]
+fn requests_reserve_their_value_until_settled() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(submit_request(5, 10, 3, 1), Error::<Test>::InsufficientFunds);
+
+		assert_ok!(submit_request(1, 10, 3, 1));
+		assert_eq!(Balances::reserved_balance(1), 30);
+		assert_ok!(amend_order(1, 0, 20, 4));
+		assert_eq!(Balances::reserved_balance(1), 80);
+		assert_noop!(amend_order(1, 0, 1_000, 2), Error::<Test>::InsufficientFunds);
+		assert_ok!(MediatorModule::cancel_order(RuntimeOrigin::signed(1), 0));
+		assert_eq!(Balances::reserved_balance(1), 0);
+
+		assert_ok!(submit_request(1, 10, 3, 1));
+		assert_ok!(submit_pv_offer(3, 5, 2, 1));
+		assert_eq!(Balances::reserved_balance(3), 0);
+		run_to_block(8);
+
+		// 5 units were bought at 2 and paid from the reserve, the rest of it is released
+		assert_eq!(Balances::reserved_balance(1), 0);
+		assert_eq!(Balances::free_balance(1), 990);
+		assert_eq!(Balances::free_balance(3), 1_010);
+		System::assert_has_event(Event::<Test>::TradesSettled(1, 10, 0).into());
+		System::assert_has_event(Event::<Test>::TradesSettled(3, 0, 10).into());
+	});
+}
+
+#[test]
+fn participants_only_pay_or_receive_the_net_value_of_their_trades() {
+	new_test_ext().execute_with(|| {
+		// 1 sells 6 units at 2 to 2 and buys 5 units at 4 from 3 in the same period
+		assert_ok!(submit_request(1, 5, 4, 1));
+		assert_ok!(submit_pv_offer(1, 6, 2, 1));
+		assert_ok!(submit_request(2, 6, 2, 1));
+		assert_ok!(submit_pv_offer(3, 5, 4, 1));
+
+		run_to_block(9);
+
+		assert_eq!(MediatorModule::period_trades(1).len(), 2);
+		// Of the 20 it owes 3, the 12 it is owed by 2 are paid to 3 directly
+		let repatriated = |from, amount| pallet_balances::Event::<Test>::ReserveRepatriated {
+			from,
+			to: 3,
+			amount,
+			destination_status: BalanceStatus::Free,
+		};
+		System::assert_has_event(repatriated(1, 8).into());
+		System::assert_has_event(repatriated(2, 12).into());
+		assert!(!System::events().iter().any(|record| {
+			matches!(
+				record.event,
+				RuntimeEvent::Balances(pallet_balances::Event::ReserveRepatriated { to: 1, .. })
+			)
+		}));
+		System::assert_has_event(Event::<Test>::TradesSettled(1, 8, 0).into());
+		System::assert_has_event(Event::<Test>::TradesSettled(2, 12, 0).into());
+		System::assert_has_event(Event::<Test>::TradesSettled(3, 0, 20).into());
+		assert_eq!(Balances::reserved_balance(1), 0);
+		assert_eq!(Balances::free_balance(1), 992);
+		assert_eq!(Balances::free_balance(2), 988);
+		assert_eq!(Balances::free_balance(3), 1_020);
+	});
+}
 
 #[test]
-fn it_works_for_default_value() {
+fn trades_not_covered_by_the_reserve_are_recorded() {
 	new_test_ext().execute_with(|| {
-		// Dispatch a signed extrinsic.
-		assert_ok!(MediatorModule::do_something(RuntimeOrigin::signed(1), 42));
-		// Read pallet storage and assert an expected result.
-		assert_eq!(MediatorModule::something(), Some(42));
+		assert_ok!(submit_request(1, 5, 2, 1));
+		assert_ok!(submit_pv_offer(3, 5, 2, 1));
+		// Something else released most of the reserve
+		Balances::unreserve(&1, 6);
+
+		run_to_block(8);
+
+		assert_eq!(
+			MediatorModule::failed_settlements(1, 1),
+			Some(FailedSettlement { reason: SettlementFailure::InsufficientReserve, unpaid: 6 })
+		);
+		System::assert_has_event(
+			Event::<Test>::SettlementFailed(1, 1, SettlementFailure::InsufficientReserve, 6).into(),
+		);
+		assert_eq!(Balances::free_balance(3), 1_004);
+		System::assert_has_event(Event::<Test>::TradesSettled(1, 4, 0).into());
 	});
 }
 
 #[test]
-fn correct_error_for_none_value() {
+fn exports_the_grid_operator_can_not_pay_for_are_recorded() {
 	new_test_ext().execute_with(|| {
-		// Ensure the expected error is thrown when no value is present.
+		let tariff =
+			GridTariff { import_price: 5, export_price: 2, import_capacity: 0, export_capacity: 5 };
+		assert_ok!(MediatorModule::set_grid_tariff(RuntimeOrigin::signed(100), tariff, None));
+		assert_ok!(submit_pv_offer(3, 4, 2, 1));
+		// The grid pays from its free balance, which nothing but transfers fund
+		Balances::make_free_balance_be(&100, 1);
+
+		run_to_block(8);
+
+		assert_eq!(
+			MediatorModule::failed_settlements(1, 100),
+			Some(FailedSettlement { reason: SettlementFailure::TransferFailed, unpaid: 8 })
+		);
+		assert_eq!(Balances::free_balance(3), 1_000);
+		System::assert_has_event(Event::<Test>::TradesSettled(3, 0, 0).into());
+	});
+}
+
+#[test]
+fn meter_readings_are_reconciled_with_the_trades_of_the_period() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(
+			MediatorModule::register_meter(RuntimeOrigin::signed(1), 7, 20, 1),
+			DispatchError::BadOrigin
+		);
+		assert_noop!(
+			MediatorModule::register_meter(RuntimeOrigin::root(), 9, 22, 6),
+			Error::<Test>::NotRegistered
+		);
+		assert_ok!(MediatorModule::register_meter(RuntimeOrigin::root(), 7, 20, 1));
+		assert_ok!(MediatorModule::register_meter(RuntimeOrigin::root(), 8, 21, 1));
+		assert_eq!(MediatorModule::meters(7), Some(Meter { key: 20, participant: 1 }));
+
+		assert_ok!(submit_request(1, 10, 3, 1));
+		assert_ok!(submit_pv_offer(3, 10, 2, 1));
+		run_to_block(8);
+		assert_eq!(MediatorModule::traded_energy(1, 1), 10);
+		assert_eq!(MediatorModule::traded_energy(1, 3), -10);
+
+		// Period 1 is delivered from block 10 up to block 20
+		assert_noop!(
+			MediatorModule::submit_meter_reading(RuntimeOrigin::signed(20), 7, 1, 9_000, 0),
+			Error::<Test>::PeriodNotDelivered
+		);
+		run_to_block(20);
+		// Cleared orders are no longer open
+		assert_eq!(MediatorModule::participants(3).map(|p| p.open_orders), Some(0));
+		assert_noop!(
+			MediatorModule::submit_meter_reading(RuntimeOrigin::signed(20), 9, 1, 9_000, 0),
+			Error::<Test>::UnknownMeter
+		);
 		assert_noop!(
-			MediatorModule::cause_error(RuntimeOrigin::signed(1)),
-			Error::<Test>::NoneValue
+			MediatorModule::submit_meter_reading(RuntimeOrigin::signed(1), 7, 1, 9_000, 0),
+			Error::<Test>::NotMeterKey
 		);
+
+		assert_ok!(MediatorModule::submit_meter_reading(RuntimeOrigin::signed(20), 7, 1, 9_000, 0));
+		assert_eq!(
+			MediatorModule::meter_readings(1, 7),
+			Some(MeterReading { imported_wh: 9_000, exported_wh: 0 })
+		);
+		System::assert_has_event(Event::<Test>::DeliveryReconciled(1, 1, -1_000).into());
+		assert_noop!(
+			MediatorModule::submit_meter_reading(RuntimeOrigin::signed(20), 7, 1, 9_000, 0),
+			Error::<Test>::ReadingExists
+		);
+
+		// The readings of all meters of the participant add up
+		let origin = RuntimeOrigin::signed(21);
+		assert_ok!(MediatorModule::submit_meter_reading(origin, 8, 1, 2_500, 300));
+		let delivery = MediatorModule::deliveries(1, 1).unwrap();
+		assert_eq!(delivery, Delivery { traded_wh: 10_000, metered_wh: 11_200 });
+		assert_eq!(delivery.deviation_wh(), 1_200);
+		System::assert_last_event(Event::<Test>::DeliveryReconciled(1, 1, 1_200).into());
+	});
+}
+
+#[test]
+fn periods_beyond_retained_periods_are_pruned_over_several_blocks() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MediatorModule::register_meter(RuntimeOrigin::root(), 7, 20, 1));
+		assert_ok!(MediatorModule::register_meter(RuntimeOrigin::root(), 8, 21, 1));
+		let ids: Vec<_> = (0..6)
+			.map(|_| MediatorModule::record_trade(1, trade(1, 3, 3, 1), Certificate::default()))
+			.collect::<Result<_, _>>()
+			.unwrap();
+		run_to_block(20);
+		assert_ok!(MediatorModule::submit_meter_reading(RuntimeOrigin::signed(20), 7, 1, 9_000, 0));
+
+		// Clearing period 3 leaves period 1 out of the last 2 periods cleared
+		run_to_block(28);
+		assert_eq!(MediatorModule::current_period(), 4);
+		assert_noop!(
+			MediatorModule::submit_meter_reading(RuntimeOrigin::signed(21), 8, 1, 1_000, 0),
+			Error::<Test>::PeriodPruned
+		);
+		assert!(ids.iter().all(|id| MediatorModule::trades(id).is_some()));
+
+		// 4 entries are pruned a block, starting with the trades
+		run_to_block(29);
+		assert!(ids[..4].iter().all(|id| MediatorModule::trades(id).is_none()));
+		assert!(ids[..4].iter().all(|id| MediatorModule::certificates(id).is_none()));
+		assert!(MediatorModule::trades(ids[4]).is_some());
+		assert_eq!(MediatorModule::traded_energy(1, 1), 6);
+		assert_eq!(MediatorModule::pruned_period(), 1);
+
+		// Followed by the traded energy, meter readings and deliveries
+		run_to_block(31);
+		assert!(MediatorModule::period_trades(1).is_empty());
+		assert_eq!(MediatorModule::trades(ids[5]), None);
+		assert_eq!(MediatorModule::traded_energy(1, 1), 0);
+		assert_eq!(MediatorModule::meter_readings(1, 7), None);
+		assert_eq!(MediatorModule::deliveries(1, 1), None);
+		assert_eq!(MediatorModule::pruned_period(), 2);
+	});
+}
+
+#[test]
+fn only_active_registered_participants_can_place_orders() {
+	new_test_ext().execute_with(|| {
+		assert_noop!(submit_pv_offer(6, 10, 3, 1), Error::<Test>::NotRegistered);
+		assert_noop!(
+			MediatorModule::register_participant(RuntimeOrigin::signed(7), Role::Producer, None, 7),
+			Error::<Test>::InsufficientFunds
+		);
+
+		let origin = RuntimeOrigin::signed(6);
+		assert_ok!(MediatorModule::register_participant(origin, Role::Producer, Some(2), 60));
+		assert_eq!(
+			MediatorModule::participants(6),
+			Some(Participant {
+				role: Role::Producer,
+				microgrid: Some(2),
+				metering_point: 60,
+				active: true,
+				deposit: 50,
+				open_orders: 0,
+			})
+		);
+		assert_eq!(Balances::reserved_balance(6), 50);
+		System::assert_last_event(Event::<Test>::ParticipantRegistered(6, Role::Producer).into());
+		assert_noop!(
+			MediatorModule::register_participant(RuntimeOrigin::signed(6), Role::Storage, None, 60),
+			Error::<Test>::AlreadyRegistered
+		);
+
+		assert_noop!(
+			MediatorModule::set_participant_active(RuntimeOrigin::signed(6), 6, false),
+			DispatchError::BadOrigin
+		);
+		assert_ok!(MediatorModule::set_participant_active(RuntimeOrigin::root(), 6, false));
+		assert_noop!(submit_pv_offer(6, 10, 3, 1), Error::<Test>::InactiveParticipant);
+		assert_ok!(MediatorModule::set_participant_active(RuntimeOrigin::root(), 6, true));
+		assert_ok!(submit_pv_offer(6, 10, 3, 1));
+		assert_eq!(MediatorModule::participants(6).map(|p| p.open_orders), Some(1));
+
+		assert_noop!(
+			MediatorModule::deregister_participant(RuntimeOrigin::signed(6)),
+			Error::<Test>::HasOpenOrders
+		);
+		assert_ok!(MediatorModule::cancel_order(RuntimeOrigin::signed(6), 0));
+		assert_ok!(MediatorModule::deregister_participant(RuntimeOrigin::signed(6)));
+		assert_eq!(MediatorModule::participants(6), None);
+		assert_eq!(Balances::reserved_balance(6), 0);
+		assert_noop!(
+			MediatorModule::deregister_participant(RuntimeOrigin::signed(6)),
+			Error::<Test>::NotRegistered
+		);
+	});
+}
+
+#[test]
+fn orders_left_by_peers_trade_with_the_grid_at_its_published_tariff() {
+	new_test_ext().execute_with(|| {
+		let current =
+			GridTariff { import_price: 5, export_price: 1, import_capacity: 1, export_capacity: 1 };
+		let scheduled =
+			GridTariff { import_price: 4, export_price: 1, import_capacity: 6, export_capacity: 4 };
+		assert_noop!(
+			MediatorModule::set_grid_tariff(RuntimeOrigin::signed(1), current, None),
+			DispatchError::BadOrigin
+		);
+		assert_noop!(
+			MediatorModule::set_grid_tariff(RuntimeOrigin::signed(100), scheduled, Some(0)),
+			Error::<Test>::GateClosed
+		);
+		assert_ok!(MediatorModule::set_grid_tariff(RuntimeOrigin::signed(100), current, None));
+		assert_ok!(MediatorModule::set_grid_tariff(RuntimeOrigin::signed(100), scheduled, Some(1)));
+		System::assert_last_event(Event::<Test>::GridTariffSet(Some(1), scheduled).into());
+
+		assert_ok!(submit_request(1, 10, 4, 1));
+		assert_ok!(submit_request(2, 8, 3, 1));
+		assert_ok!(submit_pv_offer(3, 6, 1, 1));
+		run_to_block(9);
+
+		// 2 buys all the peers offer, and is left with 2 it will not pay the grid's price for,
+		// while 1 buys as much as the grid supplies
+		let id = MediatorModule::trade_id(1, 0, &trade(6, 4, 100, 1));
+		assert_eq!(MediatorModule::trades(id), Some(trade(6, 4, 100, 1)));
+		assert_eq!(MediatorModule::certificates(id), Some(Certificate::default()));
+		assert_eq!(MediatorModule::period_trades(1).len(), 2);
+		System::assert_has_event(Event::<Test>::TradesSettled(1, 24, 0).into());
+		System::assert_has_event(Event::<Test>::TradesSettled(2, 12, 0).into());
+		System::assert_has_event(Event::<Test>::OrderUnmatched(1, 2).into());
+		System::assert_has_event(Event::<Test>::OrderUnmatched(0, 4).into());
+		assert_eq!(Balances::reserved_balance(1), 0);
+		assert_eq!(Balances::free_balance(100), 1_024);
+		// The scheduled tariff applied to its period alone
+		assert_eq!(MediatorModule::grid_tariff(), current);
+		assert_eq!(MediatorModule::scheduled_grid_tariffs(1), None);
+
+		// The grid takes what is left of offers up to the capacity of the current tariff
+		let origin = RuntimeOrigin::signed(3);
+		assert_ok!(MediatorModule::submit_offer(origin, 5, 1, 2, EnergySource::Wind));
+		run_to_block(18);
+		let id = MediatorModule::trade_id(2, 0, &trade(1, 1, 3, 100));
+		assert_eq!(MediatorModule::trades(id), Some(trade(1, 1, 3, 100)));
+		let wind = Certificate { source: EnergySource::Wind, premium: 0 };
+		assert_eq!(MediatorModule::certificates(id), Some(wind));
+		assert_eq!(Balances::free_balance(3), 1_013);
+		assert_eq!(Balances::free_balance(100), 1_023);
+		System::assert_has_event(Event::<Test>::OrderUnmatched(3, 4).into());
+	});
+}
+
+#[test]
+fn amounts_left_unserved_or_unsold_are_reported() {
+	new_test_ext().execute_with(|| {
+		// Nothing trades with the grid until it publishes a tariff
+		assert_ok!(submit_request(1, 4, 1, 1));
+		assert_ok!(submit_pv_offer(3, 3, 2, 1));
+		assert_ok!(submit_pv_offer(4, 2, 3, 1));
+		run_to_block(10);
+		System::assert_has_event(Event::<Test>::PeriodCleared(1).into());
+		assert!(MediatorModule::period_trades(1).is_empty());
+		System::assert_has_event(Event::<Test>::OrderUnmatched(0, 4).into());
+		System::assert_has_event(Event::<Test>::OrderUnmatched(1, 3).into());
+		System::assert_has_event(Event::<Test>::OrderUnmatched(2, 2).into());
+
+		// The grid buys nothing priced above its export price
+		let tariff =
+			GridTariff { import_price: 5, export_price: 2, import_capacity: 0, export_capacity: 5 };
+		assert_ok!(MediatorModule::set_grid_tariff(RuntimeOrigin::signed(100), tariff, Some(2)));
+		assert_ok!(submit_pv_offer(3, 2, 1, 2));
+		assert_ok!(submit_pv_offer(4, 2, 2, 2));
+		assert_ok!(submit_pv_offer(5, 2, 3, 2));
+		run_to_block(20);
+		System::assert_has_event(Event::<Test>::PeriodCleared(2).into());
+		assert_eq!(MediatorModule::period_trades(2).len(), 2);
+		System::assert_has_event(Event::<Test>::OrderUnmatched(5, 2).into());
+	});
+}
+
+#[test]
+fn registrations_are_limited_to_max_participants() {
+	new_test_ext().execute_with(|| {
+		MediatorModule::integrity_test();
+		let register = |who: u64, role| {
+			MediatorModule::register_participant(RuntimeOrigin::signed(who), role, None, who)
+		};
+
+		// 5 participants are registered at genesis
+		assert_ok!(register(6, Role::Storage));
+		assert_noop!(register(100, Role::Consumer), Error::<Test>::TooManyParticipants);
+
+		assert_ok!(MediatorModule::deregister_participant(RuntimeOrigin::signed(6)));
+		assert_ok!(register(100, Role::Consumer));
+	});
+}
+
+#[test]
+fn privileged_roles_are_granted_by_the_grid_or_root() {
+	new_test_ext().execute_with(|| {
+		for role in [Role::MicrogridAggregator, Role::GridOperator] {
+			assert_noop!(
+				MediatorModule::register_participant(RuntimeOrigin::signed(6), role, None, 6),
+				Error::<Test>::PrivilegedRole
+			);
+		}
+
+		let grant = MediatorModule::set_participant_role;
+		assert_noop!(
+			grant(RuntimeOrigin::signed(1), 1, Role::GridOperator),
+			DispatchError::BadOrigin
+		);
+		assert_noop!(
+			grant(RuntimeOrigin::signed(100), 6, Role::GridOperator),
+			Error::<Test>::NotRegistered
+		);
+		assert_ok!(grant(RuntimeOrigin::signed(100), 1, Role::MicrogridAggregator));
+		assert_eq!(
+			MediatorModule::participants(1).map(|p| p.role),
+			Some(Role::MicrogridAggregator)
+		);
+		System::assert_last_event(
+			Event::<Test>::ParticipantRoleChanged(1, Role::MicrogridAggregator).into(),
+		);
+		assert_ok!(grant(RuntimeOrigin::root(), 2, Role::GridOperator));
+		assert_eq!(MediatorModule::participants(2).map(|p| p.role), Some(Role::GridOperator));
+	});
+}
+
+#[test]
+fn participants_place_orders_of_the_sides_their_role_allows() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(MediatorModule::set_participant_role(RuntimeOrigin::root(), 1, Role::Consumer));
+		assert_ok!(MediatorModule::set_participant_role(RuntimeOrigin::root(), 2, Role::Producer));
+
+		assert_noop!(submit_pv_offer(1, 10, 3, 1), Error::<Test>::SideNotAllowed);
+		assert_noop!(submit_request(2, 10, 3, 1), Error::<Test>::SideNotAllowed);
+		assert_ok!(submit_request(1, 10, 3, 1));
+		assert_ok!(submit_pv_offer(2, 10, 3, 1));
+		// Prosumers place both
+		assert_ok!(submit_request(3, 10, 3, 1));
+		assert_ok!(submit_pv_offer(3, 10, 3, 1));
 	});
 }
@@ -0,0 +1,277 @@
+//! Weights for pallet_mediator
+//!
+//! The storage accesses are counted from the code of each call. The execution times are estimates
+//! until the weights are generated on reference hardware with
+//!
+//! ```text
+//! ./target/release/polkem-mediator-node benchmark pallet \
+//!     --chain dev --pallet pallet_mediator --extrinsic '*' --steps 50 --repeat 20 \
+//!     --output pallets/mediator/src/weights.rs
+//! ```
+
+#![cfg_attr(rustfmt, rustfmt_skip)]
+#![allow(unused_parens)]
+#![allow(unused_imports)]
+
+use frame_support::{
+	sp_std::marker::PhantomData,
+	traits::Get,
+	weights::{constants::RocksDbWeight, Weight},
+};
+
+/// Weight functions needed for pallet_mediator.
+pub trait WeightInfo {
+	fn submit_request() -> Weight;
+	fn submit_offer() -> Weight;
+	fn cancel_order() -> Weight;
+	fn amend_order() -> Weight;
+	fn register_meter() -> Weight;
+	fn submit_meter_reading() -> Weight;
+	fn register_participant() -> Weight;
+	fn deregister_participant() -> Weight;
+	fn set_participant_active() -> Weight;
+	fn set_grid_tariff() -> Weight;
+	fn set_participant_role() -> Weight;
+	/// Processing `o` orders of the period being cleared, each forming up to one trade
+	fn clear_orders(o: u32) -> Weight;
+	/// Removing a cleared period of up to `n` requests and `n` offers, and settling what each
+	/// of its participants owes or is owed
+	fn finish_clearing(n: u32) -> Weight;
+	/// Removing `t` trades of an earlier period along with their certificates, and `e` entries
+	/// of its traded energy, meter readings, deliveries and failed settlements
+	fn prune_period(t: u32, e: u32) -> Weight;
+}
+
+/// Weights for pallet_mediator using the Substrate node and recommended hardware.
+pub struct SubstrateWeight<T>(PhantomData<T>);
+impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
+	// Storage: Mediator Participants (r:1 w:1)
+	// Storage: Mediator CurrentPeriod (r:1 w:0)
+	// Storage: Mediator NextOrderId (r:1 w:1)
+	// Storage: Mediator Requests (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Mediator OrderLocations (r:0 w:1)
+	fn submit_request() -> Weight {
+		Weight::from_ref_time(48_000_000 as u64)
+			.saturating_add(T::DbWeight::get().reads(5 as u64))
+			.saturating_add(T::DbWeight::get().writes(5 as u64))
+	}
+	// Storage: Mediator Participants (r:1 w:1)
+	// Storage: Mediator CurrentPeriod (r:1 w:0)
+	// Storage: Mediator NextOrderId (r:1 w:1)
+	// Storage: Mediator Offers (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Mediator OrderLocations (r:0 w:1)
+	fn submit_offer() -> Weight {
+		Weight::from_ref_time(48_000_000 as u64)
+			.saturating_add(T::DbWeight::get().reads(5 as u64))
+			.saturating_add(T::DbWeight::get().writes(5 as u64))
+	}
+	// Storage: Mediator OrderLocations (r:1 w:1)
+	// Storage: Mediator Requests (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Mediator Participants (r:1 w:1)
+	fn cancel_order() -> Weight {
+		Weight::from_ref_time(45_000_000 as u64)
+			.saturating_add(T::DbWeight::get().reads(4 as u64))
+			.saturating_add(T::DbWeight::get().writes(4 as u64))
+	}
+	// Storage: Mediator OrderLocations (r:1 w:0)
+	// Storage: Mediator Requests (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	fn amend_order() -> Weight {
+		Weight::from_ref_time(45_000_000 as u64)
+			.saturating_add(T::DbWeight::get().reads(3 as u64))
+			.saturating_add(T::DbWeight::get().writes(2 as u64))
+	}
+	// Storage: Mediator Participants (r:1 w:0)
+	// Storage: Mediator Meters (r:0 w:1)
+	fn register_meter() -> Weight {
+		Weight::from_ref_time(20_000_000 as u64)
+			.saturating_add(T::DbWeight::get().reads(1 as u64))
+			.saturating_add(T::DbWeight::get().writes(1 as u64))
+	}
+	// Storage: Mediator Meters (r:1 w:0)
+	// Storage: Mediator CurrentPeriod (r:1 w:0)
+	// Storage: Mediator MeterReadings (r:1 w:1)
+	// Storage: Mediator TradedEnergy (r:1 w:0)
+	// Storage: Mediator Deliveries (r:1 w:1)
+	fn submit_meter_reading() -> Weight {
+		Weight::from_ref_time(35_000_000 as u64)
+			.saturating_add(T::DbWeight::get().reads(5 as u64))
+			.saturating_add(T::DbWeight::get().writes(2 as u64))
+	}
+	// Storage: Mediator Participants (r:1 w:1)
+	// Storage: Mediator CounterForParticipants (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	fn register_participant() -> Weight {
+		Weight::from_ref_time(38_000_000 as u64)
+			.saturating_add(T::DbWeight::get().reads(3 as u64))
+			.saturating_add(T::DbWeight::get().writes(3 as u64))
+	}
+	// Storage: Mediator Participants (r:1 w:1)
+	// Storage: Mediator CounterForParticipants (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	fn deregister_participant() -> Weight {
+		Weight::from_ref_time(36_000_000 as u64)
+			.saturating_add(T::DbWeight::get().reads(3 as u64))
+			.saturating_add(T::DbWeight::get().writes(3 as u64))
+	}
+	// Storage: Mediator Participants (r:1 w:1)
+	fn set_participant_active() -> Weight {
+		Weight::from_ref_time(22_000_000 as u64)
+			.saturating_add(T::DbWeight::get().reads(1 as u64))
+			.saturating_add(T::DbWeight::get().writes(1 as u64))
+	}
+	// Storage: Mediator ScheduledGridTariffs (r:0 w:1)
+	fn set_grid_tariff() -> Weight {
+		Weight::from_ref_time(18_000_000 as u64)
+			.saturating_add(T::DbWeight::get().writes(1 as u64))
+	}
+	// Storage: Mediator Participants (r:1 w:1)
+	fn set_participant_role() -> Weight {
+		Weight::from_ref_time(22_000_000 as u64)
+			.saturating_add(T::DbWeight::get().reads(1 as u64))
+			.saturating_add(T::DbWeight::get().writes(1 as u64))
+	}
+	// Storage: Mediator Clearing (r:1 w:1)
+	// Storage: Mediator ScheduledGridTariffs (r:1 w:1)
+	// Storage: Mediator CurrentGridTariff (r:1 w:1)
+	// Storage: Mediator Requests (r:2 w:1)
+	// Storage: Mediator Offers (r:2 w:1)
+	// Storage: Mediator PeriodTrades (r:1 w:1)
+	// Storage: Mediator TradedEnergy (r:2 w:2)
+	// Storage: Mediator Trades (r:0 w:1)
+	// Storage: Mediator Certificates (r:0 w:1)
+	// Storage: Mediator PeriodStatements (r:2 w:2)
+	/// The range of component `o` is `[1, 3 * MaxOrdersPerPeriod]`.
+	fn clear_orders(o: u32, ) -> Weight {
+		Weight::from_ref_time(60_000_000 as u64)
+			.saturating_add(Weight::from_ref_time(40_000_000 as u64).saturating_mul(o as u64))
+			.saturating_add(T::DbWeight::get().reads(7 as u64))
+			.saturating_add(T::DbWeight::get().reads((5 as u64).saturating_mul(o as u64)))
+			.saturating_add(T::DbWeight::get().writes(5 as u64))
+			.saturating_add(T::DbWeight::get().writes((8 as u64).saturating_mul(o as u64)))
+	}
+	// Storage: Mediator OrderLocations (r:0 w:2)
+	// Storage: Mediator Participants (r:2 w:2)
+	// Storage: Mediator Requests (r:0 w:1)
+	// Storage: Mediator Offers (r:0 w:1)
+	// Storage: Mediator Clearing (r:0 w:1)
+	// Storage: Mediator CurrentPeriod (r:0 w:1)
+	// Storage: Mediator PeriodStatements (r:2 w:2)
+	// Storage: System Account (r:4 w:4)
+	// Storage: Mediator FailedSettlements (r:2 w:2)
+	/// The range of component `n` is `[1, MaxOrdersPerPeriod]`.
+	fn finish_clearing(n: u32, ) -> Weight {
+		Weight::from_ref_time(25_000_000 as u64)
+			.saturating_add(Weight::from_ref_time(45_000_000 as u64).saturating_mul(n as u64))
+			.saturating_add(T::DbWeight::get().reads((10 as u64).saturating_mul(n as u64)))
+			.saturating_add(T::DbWeight::get().writes(4 as u64))
+			.saturating_add(T::DbWeight::get().writes((12 as u64).saturating_mul(n as u64)))
+	}
+	// Storage: Mediator PrunedPeriod (r:1 w:1)
+	// Storage: Mediator CurrentPeriod (r:1 w:0)
+	// Storage: Mediator PeriodTrades (r:1 w:1)
+	// Storage: Mediator Trades (r:0 w:1)
+	// Storage: Mediator Certificates (r:0 w:1)
+	// Storage: Mediator TradedEnergy (r:1 w:1)
+	// Storage: Mediator MeterReadings (r:1 w:1)
+	// Storage: Mediator Deliveries (r:1 w:1)
+	// Storage: Mediator FailedSettlements (r:1 w:1)
+	/// The range of component `t` is `[0, MaxPrunedPerBlock]`.
+	/// The range of component `e` is `[0, MaxPrunedPerBlock]`.
+	fn prune_period(t: u32, e: u32, ) -> Weight {
+		Weight::from_ref_time(12_000_000 as u64)
+			.saturating_add(Weight::from_ref_time(4_000_000 as u64).saturating_mul(t as u64))
+			.saturating_add(Weight::from_ref_time(3_000_000 as u64).saturating_mul(e as u64))
+			.saturating_add(T::DbWeight::get().reads(4 as u64))
+			.saturating_add(T::DbWeight::get().reads((1 as u64).saturating_mul(e as u64)))
+			.saturating_add(T::DbWeight::get().writes(2 as u64))
+			.saturating_add(T::DbWeight::get().writes((2 as u64).saturating_mul(t as u64)))
+			.saturating_add(T::DbWeight::get().writes((1 as u64).saturating_mul(e as u64)))
+	}
+}
+
+// For backwards compatibility and tests
+impl WeightInfo for () {
+	fn submit_request() -> Weight {
+		Weight::from_ref_time(48_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().reads(5 as u64))
+			.saturating_add(RocksDbWeight::get().writes(5 as u64))
+	}
+	fn submit_offer() -> Weight {
+		Weight::from_ref_time(48_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().reads(5 as u64))
+			.saturating_add(RocksDbWeight::get().writes(5 as u64))
+	}
+	fn cancel_order() -> Weight {
+		Weight::from_ref_time(45_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().reads(4 as u64))
+			.saturating_add(RocksDbWeight::get().writes(4 as u64))
+	}
+	fn amend_order() -> Weight {
+		Weight::from_ref_time(45_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().reads(3 as u64))
+			.saturating_add(RocksDbWeight::get().writes(2 as u64))
+	}
+	fn register_meter() -> Weight {
+		Weight::from_ref_time(20_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().reads(1 as u64))
+			.saturating_add(RocksDbWeight::get().writes(1 as u64))
+	}
+	fn submit_meter_reading() -> Weight {
+		Weight::from_ref_time(35_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().reads(5 as u64))
+			.saturating_add(RocksDbWeight::get().writes(2 as u64))
+	}
+	fn register_participant() -> Weight {
+		Weight::from_ref_time(38_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().reads(3 as u64))
+			.saturating_add(RocksDbWeight::get().writes(3 as u64))
+	}
+	fn deregister_participant() -> Weight {
+		Weight::from_ref_time(36_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().reads(3 as u64))
+			.saturating_add(RocksDbWeight::get().writes(3 as u64))
+	}
+	fn set_participant_active() -> Weight {
+		Weight::from_ref_time(22_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().reads(1 as u64))
+			.saturating_add(RocksDbWeight::get().writes(1 as u64))
+	}
+	fn set_grid_tariff() -> Weight {
+		Weight::from_ref_time(18_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().writes(1 as u64))
+	}
+	fn set_participant_role() -> Weight {
+		Weight::from_ref_time(22_000_000 as u64)
+			.saturating_add(RocksDbWeight::get().reads(1 as u64))
+			.saturating_add(RocksDbWeight::get().writes(1 as u64))
+	}
+	fn clear_orders(o: u32, ) -> Weight {
+		Weight::from_ref_time(60_000_000 as u64)
+			.saturating_add(Weight::from_ref_time(40_000_000 as u64).saturating_mul(o as u64))
+			.saturating_add(RocksDbWeight::get().reads(7 as u64))
+			.saturating_add(RocksDbWeight::get().reads((5 as u64).saturating_mul(o as u64)))
+			.saturating_add(RocksDbWeight::get().writes(5 as u64))
+			.saturating_add(RocksDbWeight::get().writes((8 as u64).saturating_mul(o as u64)))
+	}
+	fn finish_clearing(n: u32, ) -> Weight {
+		Weight::from_ref_time(25_000_000 as u64)
+			.saturating_add(Weight::from_ref_time(45_000_000 as u64).saturating_mul(n as u64))
+			.saturating_add(RocksDbWeight::get().reads((10 as u64).saturating_mul(n as u64)))
+			.saturating_add(RocksDbWeight::get().writes(4 as u64))
+			.saturating_add(RocksDbWeight::get().writes((12 as u64).saturating_mul(n as u64)))
+	}
+	fn prune_period(t: u32, e: u32, ) -> Weight {
+		Weight::from_ref_time(12_000_000 as u64)
+			.saturating_add(Weight::from_ref_time(4_000_000 as u64).saturating_mul(t as u64))
+			.saturating_add(Weight::from_ref_time(3_000_000 as u64).saturating_mul(e as u64))
+			.saturating_add(RocksDbWeight::get().reads(4 as u64))
+			.saturating_add(RocksDbWeight::get().reads((1 as u64).saturating_mul(e as u64)))
+			.saturating_add(RocksDbWeight::get().writes(2 as u64))
+			.saturating_add(RocksDbWeight::get().writes((2 as u64).saturating_mul(t as u64)))
+			.saturating_add(RocksDbWeight::get().writes((1 as u64).saturating_mul(e as u64)))
+	}
+}
@@ -15,7 +15,10 @@ use sp_api::impl_runtime_apis;
 use sp_core::{crypto::KeyTypeId, OpaqueMetadata};
 use sp_runtime::{
 	create_runtime_str, generic, impl_opaque_keys,
-	traits::{AccountIdLookup, BlakeTwo256, Block as BlockT, IdentifyAccount, Verify},
+	traits::{
+		AccountIdConversion, AccountIdLookup, BlakeTwo256, Block as BlockT, IdentifyAccount,
+		Verify,
+	},
 	transaction_validity::{TransactionSource, TransactionValidity},
 	ApplyExtrinsicResult, MultiSignature,
 };
@@ -29,7 +32,7 @@ use frame_support::{
 	construct_runtime,
 	dispatch::DispatchClass,
 	parameter_types,
-	traits::{ConstU32, ConstU64, ConstU8, Everything},
+	traits::{ConstU16, ConstU32, ConstU64, ConstU8, Everything},
 	weights::{
 		constants::WEIGHT_REF_TIME_PER_SECOND, ConstantMultiplier, Weight, WeightToFeeCoefficient,
 		WeightToFeeCoefficients, WeightToFeePolynomial,
@@ -448,10 +451,51 @@ impl pallet_collator_selection::Config for Runtime {
 	type WeightInfo = ();
 }
 
+parameter_types! {
+	/// Energy is traded for delivery intervals of 15 minutes
+	pub const MarketPeriod: BlockNumber = 15 * MINUTES;
+	/// Orders for an interval are locked and cleared 5 minutes before it starts
+	pub const GateClosureOffset: BlockNumber = 5 * MINUTES;
+	pub const GridOperatorPalletId: PalletId = PalletId(*b"med/grid");
+	/// Buys and sells energy on behalf of the grid, paying for exports to the grid from its free
+	/// balance. The chain spec endows it at genesis, and it has to be topped up by transfers as
+	/// long as the grid buys more than it sells.
+	pub GridOperator: AccountId = GridOperatorPalletId::get().into_account_truncating();
+	pub const RegistrationDeposit: Balance = 10 * UNIT;
+	/// Held for every open order, so that filling the books of a period is not free
+	pub const OrderDeposit: Balance = UNIT;
+}
+
 /// Configure the pallet mediator in pallets/mediator.
 impl pallet_mediator::Config for Runtime {
 	type RuntimeEvent = RuntimeEvent;
 	type Currency = Balances;
+	// A full book of `3 * 64` steps, cleared 16 steps a block, takes 12 of the 25 blocks between
+	// gate closure and delivery. The weights are estimates until they are benchmarked, so what
+	// share of a block those steps take is not known yet, and these limits may have to change
+	type MaxOrdersPerPeriod = ConstU32<64>;
+	// A single account can take no more than a sixteenth of either book, up to a day ahead
+	type MaxOrdersPerAccount = ConstU32<4>;
+	type MaxPeriodsAhead = ConstU64<{ 24 * 4 }>;
+	type OrderDeposit = OrderDeposit;
+	type MaxTradesPerPeriod = ConstU32<256>;
+	type MaxParticipants = ConstU32<10_000>;
+	type MinOrderAmount = ConstU16<1>;
+	type MaxOrderAmount = ConstU16<{ u16::MAX }>;
+	type MinPrice = ConstU8<1>;
+	type MaxPrice = ConstU8<{ u8::MAX }>;
+	type PeriodLength = MarketPeriod;
+	type GateClosureOffset = GateClosureOffset;
+	type MaxOrdersPerBlock = ConstU32<16>;
+	type GridOperator = GridOperator;
+	type WattHoursPerUnit = ConstU32<1_000>;
+	type RegistrationDeposit = RegistrationDeposit;
+	// A week of periods. Pruning 128 entries a block keeps up with periods of up to 9_600 trades,
+	// meter readings and deliveries, at a share of the block not known until it is benchmarked
+	type RetainedPeriods = ConstU32<{ 7 * 24 * 4 }>;
+	type MaxPrunedPerBlock = ConstU32<128>;
+	type GridOrigin = EnsureRoot<AccountId>;
+	type WeightInfo = pallet_mediator::weights::SubstrateWeight<Runtime>;
 }
 
 // Create the runtime by composing the FRAME pallets that were previously configured.
@@ -487,7 +531,7 @@ construct_runtime!(
 		DmpQueue: cumulus_pallet_dmp_queue::{Pallet, Call, Storage, Event<T>} = 33,
 
 		// Mediator
-		Mediator: pallet_mediator::{Pallet, Call, Storage, Event<T>}  = 40,
+		Mediator: pallet_mediator::{Pallet, Call, Storage, Config<T>, Event<T>}  = 40,
 	}
 );
 
@@ -504,6 +548,7 @@ mod benches {
 		[pallet_timestamp, Timestamp]
 		[pallet_collator_selection, CollatorSelection]
 		[cumulus_pallet_xcmp_queue, XcmpQueue]
+		[pallet_mediator, Mediator]
 	);
 }
 